@@ -0,0 +1,68 @@
+//! Times a few scripts that stress value handling, to compare the default
+//! `Value` representation with the NaN-boxed one:
+//!
+//! ```text
+//! cargo bench --no-default-features
+//! cargo bench --no-default-features --features nan_boxing
+//! ```
+
+use std::time::{Duration, Instant};
+
+use lox_bytecode::VM;
+
+const RUNS: usize = 5;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+         print fib(25);",
+    ),
+    (
+        "arithmetic",
+        "var sum = 0;
+         for (var i = 0; i < 1000000; i = i + 1) { sum = sum + i * 2 - i / 2; }
+         print sum;",
+    ),
+    (
+        "properties",
+        "class Point { init(x, y) { this.x = x; this.y = y; } }
+         var p = Point(0, 0);
+         for (var i = 0; i < 300000; i = i + 1) { p.x = p.x + 1; p.y = p.x + p.y; }
+         print p.y;",
+    ),
+    (
+        "closures",
+        "fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+         var total = 0;
+         for (var i = 0; i < 100000; i = i + 1) { var c = counter(); c(); total = total + c(); }
+         print total;",
+    ),
+    (
+        "strings",
+        "var s = \"\";
+         for (var i = 0; i < 2000; i = i + 1) { s = s + \"x\"; }
+         print s == s + \"\";",
+    ),
+];
+
+fn time(source: &str) -> Duration {
+    let mut vm = VM::new();
+    vm.set_print_callback(|_| {});
+    let start = Instant::now();
+    vm.interpret(source).expect("benchmark script failed");
+    start.elapsed()
+}
+
+fn main() {
+    let representation = if cfg!(feature = "nan_boxing") {
+        "nan-boxed"
+    } else {
+        "enum"
+    };
+    println!("value representation: {representation}");
+    for (name, source) in SCRIPTS {
+        let best = (0..RUNS).map(|_| time(source)).min().unwrap();
+        println!("{name:<12} {:>10.2} ms", best.as_secs_f64() * 1000.0);
+    }
+}
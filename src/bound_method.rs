@@ -1,35 +1,46 @@
-use std::rc::Rc;
 use std::fmt::{Display, Result, Formatter};
 
 use crate::closure::*;
+use crate::gc::*;
 use crate::value::*;
 
 #[derive(Debug)]
 pub struct BoundMethod {
     receiver: Value,
-    method: Rc<Closure>
+    method: Gc<Closure>
 }
 
 impl BoundMethod {
-    pub fn new(receiver: &Value, method: &Rc<Closure>) -> Self {
+    pub fn new(receiver: &Value, method: &Gc<Closure>) -> Self {
         Self{
-            receiver:receiver.clone(),
-            method: Rc::clone(method)
+            receiver:*receiver,
+            method: *method
         }
     }
 
-    pub fn get_closure(&self) -> Rc<Closure> {
-        Rc::clone(&self.method)
+    pub fn get_closure(&self) -> Gc<Closure> {
+        self.method
     }
 
     pub fn get_recevier(&self) -> Value {
-        self.receiver.clone()
+        self.receiver
     }
     
 }
 
+impl Trace for BoundMethod {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark_value(&self.receiver);
+        tracer.mark(self.method);
+    }
+}
+
+impl HeapObject for BoundMethod {
+    const KIND: ObjectKind = ObjectKind::BoundMethod;
+}
+
 impl Display for BoundMethod {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-       self.method.fmt(f)
+       self.method.get().fmt(f)
     }
 }
\ No newline at end of file
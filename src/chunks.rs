@@ -1,5 +1,9 @@
 use std::fmt::Display;
+use std::rc::Rc;
+#[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
+use std::io::{self, Write};
 
+use crate::span::*;
 use crate::value::*;
 
 pub enum OpCode {
@@ -39,14 +43,30 @@ pub enum OpCode {
     Invoke,
     Inherit,
     GetSuper,
-    SuperInoke
+    SuperInoke,
+    BuildList,
+    GetIndex,
+    SetIndex,
+    GetSlice,
+    BuildMap,
+    Modulo,
+    Power,
+    IntDivide,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Chunk {
     code: Vec<u8>,
-    lines: Vec<usize>,
+    spans: Vec<Span>,
     constants: ValueArray,
+    // The text the spans point into.
+    source: Rc<str>,
 }
 
 #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
@@ -60,23 +80,24 @@ impl Chunk {
     pub fn new() -> Self {
         Self {
             code: Vec::new(),
-            lines: Vec::new(),
+            spans: Vec::new(),
             constants: ValueArray::new(),
+            source: Rc::default(),
         }
     }
 
-    pub fn write(&mut self, byte: u8, line: usize) {
+    pub fn write(&mut self, byte: u8, span: Span) {
         self.code.push(byte);
-        self.lines.push(line);
+        self.spans.push(span);
     }
 
     pub fn write_at(&mut self, offset: usize, byte: u8) {
         self.code[offset] = byte;
     }
 
-    pub fn _write_opcode(&mut self, code: OpCode, line: usize) {
+    pub fn _write_opcode(&mut self, code: OpCode, span: Span) {
         self.code.push(code.into());
-        self.lines.push(line);
+        self.spans.push(span);
     }
 
     pub fn read(&self, ip: usize) -> u8 {
@@ -84,7 +105,21 @@ impl Chunk {
     }
 
     pub fn get_line(&self, ip: usize) -> usize {
-        self.lines[ip]
+        self.spans[ip].line
+    }
+
+    /// The source range the byte at `ip` was compiled from.
+    pub fn get_span(&self, ip: usize) -> Span {
+        self.spans[ip]
+    }
+
+    /// The source text the chunk was compiled from.
+    pub fn source(&self) -> Rc<str> {
+        Rc::clone(&self.source)
+    }
+
+    pub fn set_source(&mut self, source: Rc<str>) {
+        self.source = source;
     }
 
     pub fn add_constant(&mut self, value: Value) -> Option<u8> {
@@ -92,70 +127,75 @@ impl Chunk {
         u8::try_from(idx).map_err(|_| ()).ok()
     }
 
-    pub fn get_constant(&self, index: usize) -> &Value {
+    pub fn get_constant(&self, index: usize) -> Value {
         self.constants.read_value(index)
     }
 
+    pub fn constants(&self) -> impl Iterator<Item = Value> {
+        self.constants.iter()
+    }
+
     pub fn count(&self) -> usize {
-        self.lines.len()
+        self.spans.len()
     }
 
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    pub fn disassemble<T: Into<String>>(&self, name: T) {
-        println!("== {} ==", name.into());
+    pub fn disassemble<T: Into<String>>(&self, out: &mut dyn Write, name: T) -> io::Result<()> {
+        writeln!(out, "== {} ==", name.into())?;
 
         let mut offset = 0;
         while offset < self.code.len() {
-            offset = self.disassemble_instruction(offset)
+            offset = self.disassemble_instruction(out, offset)?
         }
+        Ok(())
     }
 
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    pub fn disassemble_instruction(&self, offset: usize) -> usize {
+    pub fn disassemble_instruction(&self, out: &mut dyn Write, offset: usize) -> io::Result<usize> {
         use JumpStyle::*;
-        print!("{:04} ", offset);
-        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("   | ")
+        write!(out, "{:04} ", offset)?;
+        if offset > 0 && self.get_line(offset) == self.get_line(offset - 1) {
+            write!(out, "   | ")?;
         } else {
-            print!("{:4} ", self.lines[offset]);
+            write!(out, "{:4} ", self.get_line(offset))?;
         }
 
         let instruction: OpCode = self.code[offset].into();
         match instruction {
-            OpCode::Constant => self.constant_instruction("OP_CONSTANT", offset),
-            OpCode::Return => self.simple_instruction("OP_RETURN", offset),
-            OpCode::Negate => self.simple_instruction("OP_NEGATE", offset),
-            OpCode::Add => self.simple_instruction("OP_ADD", offset),
-            OpCode::Subtract => self.simple_instruction("OP_SUBTRACT", offset),
-            OpCode::Multiply => self.simple_instruction("OP_MULTIPLY", offset),
-            OpCode::Divide => self.simple_instruction("OP_DIVIDE", offset),
-            OpCode::Nil => self.simple_instruction("OP_NIL", offset),
-            OpCode::True => self.simple_instruction("OP_TRUE", offset),
-            OpCode::False => self.simple_instruction("OP_FALSE", offset),
-            OpCode::Not => self.simple_instruction("OP_NOT", offset),
-            OpCode::Equal => self.simple_instruction("OP_EQUAL", offset),
-            OpCode::Greater => self.simple_instruction("OP_GREATER", offset),
-            OpCode::Less => self.simple_instruction("OP_LESS", offset),
-            OpCode::Print => self.simple_instruction("OP_PRINT", offset),
-            OpCode::Pop => self.simple_instruction("OP_POP", offset),
-            OpCode::DefineGlobal => self.constant_instruction("OP_DEFINE_GLOBAL", offset),
-            OpCode::GetGlobal => self.constant_instruction("OP_GET_GLOBAL", offset),
-            OpCode::SetGlobal => self.constant_instruction("OP_SET_GLOBAL", offset),
-            OpCode::GetLocal => self.byte_instruction("OP_GET_LOCAL", offset),
-            OpCode::SetLocal => self.byte_instruction("OP_SET_LOCAL", offset),
-            OpCode::JumpIfFalse => self.jump_instruction("OP_JUMP_IF_FALSE", Forwards, offset),
-            OpCode::Jump => self.jump_instruction("OP_JUMP", Forwards, offset),
-            OpCode::Loop => self.jump_instruction("OP_LOOP", Backwards, offset),
-            OpCode::Call => self.byte_instruction("OP_CALL", offset),
+            OpCode::Constant => self.constant_instruction(out, "OP_CONSTANT", offset),
+            OpCode::Return => self.simple_instruction(out, "OP_RETURN", offset),
+            OpCode::Negate => self.simple_instruction(out, "OP_NEGATE", offset),
+            OpCode::Add => self.simple_instruction(out, "OP_ADD", offset),
+            OpCode::Subtract => self.simple_instruction(out, "OP_SUBTRACT", offset),
+            OpCode::Multiply => self.simple_instruction(out, "OP_MULTIPLY", offset),
+            OpCode::Divide => self.simple_instruction(out, "OP_DIVIDE", offset),
+            OpCode::Nil => self.simple_instruction(out, "OP_NIL", offset),
+            OpCode::True => self.simple_instruction(out, "OP_TRUE", offset),
+            OpCode::False => self.simple_instruction(out, "OP_FALSE", offset),
+            OpCode::Not => self.simple_instruction(out, "OP_NOT", offset),
+            OpCode::Equal => self.simple_instruction(out, "OP_EQUAL", offset),
+            OpCode::Greater => self.simple_instruction(out, "OP_GREATER", offset),
+            OpCode::Less => self.simple_instruction(out, "OP_LESS", offset),
+            OpCode::Print => self.simple_instruction(out, "OP_PRINT", offset),
+            OpCode::Pop => self.simple_instruction(out, "OP_POP", offset),
+            OpCode::DefineGlobal => self.constant_instruction(out, "OP_DEFINE_GLOBAL", offset),
+            OpCode::GetGlobal => self.constant_instruction(out, "OP_GET_GLOBAL", offset),
+            OpCode::SetGlobal => self.constant_instruction(out, "OP_SET_GLOBAL", offset),
+            OpCode::GetLocal => self.byte_instruction(out, "OP_GET_LOCAL", offset),
+            OpCode::SetLocal => self.byte_instruction(out, "OP_SET_LOCAL", offset),
+            OpCode::JumpIfFalse => self.jump_instruction(out, "OP_JUMP_IF_FALSE", Forwards, offset),
+            OpCode::Jump => self.jump_instruction(out, "OP_JUMP", Forwards, offset),
+            OpCode::Loop => self.jump_instruction(out, "OP_LOOP", Backwards, offset),
+            OpCode::Call => self.byte_instruction(out, "OP_CALL", offset),
             OpCode::Closure => {
                 let mut i = offset + 1;
                 let constant = self.code[i];
                 i += 1;
-                print!("{:-16} {:4} ", "OP_CLOSURE", constant);
-                self.constants.print_value(constant as usize);
-                println!();
+                write!(out, "{:-16} {:4} ", "OP_CLOSURE", constant)?;
+                self.constants.write_value(out, constant as usize)?;
+                writeln!(out)?;
                 if let Value::Func(function) = self.constants.read_value(constant as usize) {
-                    for _j in 0..function.upvalue() {
+                    for _j in 0..function.get().upvalue() {
                         let is_local = if self.code[i] == 0 {
                             "upvalue"
                         } else {
@@ -164,51 +204,71 @@ impl Chunk {
                         i += 1;
                         let index = self.code[i];
                         i += 1;
-                        println!("{:04}      |                     {is_local} {index}", i - 2)
+                        writeln!(out, "{:04}      |                     {is_local} {index}", i - 2)?;
                     }
                 } else {
                     panic!("No function at position {constant}")
                 }
-                i
+                Ok(i)
             }
-            OpCode::GetUpvalue => self.byte_instruction("OP_GET_UPVALUE", offset),
-            OpCode::SetUpvalue => self.byte_instruction("OP_SET_UPVALUE", offset),
-            OpCode::CloseUpvalue => self.simple_instruction("OP_CLOSE_UPVALUE", offset),
-            OpCode::Class => self.constant_instruction("OP_CLASS", offset),
-            OpCode::GetProperty => self.constant_instruction("OP_GET_PROPERTY", offset),
-            OpCode::SetProperty => self.constant_instruction("OP_SET_PROPERTY", offset),
-            OpCode::Method => self.constant_instruction("OP_METHOD", offset),
-            OpCode::Invoke => self.invoke_instruction("OP_INVOKE", offset),
-            OpCode::Inherit => self.simple_instruction("OP_INHERIT", offset),
-            OpCode::GetSuper => self.constant_instruction("OP_GETSUPER", offset),
-            OpCode::SuperInoke => self.invoke_instruction("OP_SUPER_INVOKE", offset),
+            OpCode::GetUpvalue => self.byte_instruction(out, "OP_GET_UPVALUE", offset),
+            OpCode::SetUpvalue => self.byte_instruction(out, "OP_SET_UPVALUE", offset),
+            OpCode::CloseUpvalue => self.simple_instruction(out, "OP_CLOSE_UPVALUE", offset),
+            OpCode::Class => self.constant_instruction(out, "OP_CLASS", offset),
+            OpCode::GetProperty => self.constant_instruction(out, "OP_GET_PROPERTY", offset),
+            OpCode::SetProperty => self.constant_instruction(out, "OP_SET_PROPERTY", offset),
+            OpCode::Method => self.constant_instruction(out, "OP_METHOD", offset),
+            OpCode::Invoke => self.invoke_instruction(out, "OP_INVOKE", offset),
+            OpCode::Inherit => self.simple_instruction(out, "OP_INHERIT", offset),
+            OpCode::GetSuper => self.constant_instruction(out, "OP_GETSUPER", offset),
+            OpCode::SuperInoke => self.invoke_instruction(out, "OP_SUPER_INVOKE", offset),
+            OpCode::BuildList => self.byte_instruction(out, "OP_BUILD_LIST", offset),
+            OpCode::GetIndex => self.simple_instruction(out, "OP_GET_INDEX", offset),
+            OpCode::SetIndex => self.simple_instruction(out, "OP_SET_INDEX", offset),
+            OpCode::GetSlice => self.simple_instruction(out, "OP_GET_SLICE", offset),
+            OpCode::BuildMap => self.byte_instruction(out, "OP_BUILD_MAP", offset),
+            OpCode::Modulo => self.simple_instruction(out, "OP_MODULO", offset),
+            OpCode::Power => self.simple_instruction(out, "OP_POWER", offset),
+            OpCode::IntDivide => self.simple_instruction(out, "OP_INT_DIVIDE", offset),
+            OpCode::BitAnd => self.simple_instruction(out, "OP_BIT_AND", offset),
+            OpCode::BitOr => self.simple_instruction(out, "OP_BIT_OR", offset),
+            OpCode::BitXor => self.simple_instruction(out, "OP_BIT_XOR", offset),
+            OpCode::BitNot => self.simple_instruction(out, "OP_BIT_NOT", offset),
+            OpCode::ShiftLeft => self.simple_instruction(out, "OP_SHIFT_LEFT", offset),
+            OpCode::ShiftRight => self.simple_instruction(out, "OP_SHIFT_RIGHT", offset),
             
         }
     }
 
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    fn simple_instruction(&self, name: &str, offset: usize) -> usize {
-        println!("{name}");
-        offset + 1
+    fn simple_instruction(&self, out: &mut dyn Write, name: &str, offset: usize) -> io::Result<usize> {
+        writeln!(out, "{name}")?;
+        Ok(offset + 1)
     }
 
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    fn byte_instruction(&self, name: &str, offset: usize) -> usize {
+    fn byte_instruction(&self, out: &mut dyn Write, name: &str, offset: usize) -> io::Result<usize> {
         let slot = self.code[offset + 1];
-        println!("{:-16} {:4}", name, slot);
-        offset + 2
+        writeln!(out, "{:-16} {:4}", name, slot)?;
+        Ok(offset + 2)
     }
 
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    fn jump_instruction(&self, name: &str, jump_style: JumpStyle, offset: usize) -> usize {
+    fn jump_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        jump_style: JumpStyle,
+        offset: usize,
+    ) -> io::Result<usize> {
         let jump = self.get_jump_offset(offset + 1);
         let jump_to = if matches!(jump_style, JumpStyle::Forwards) {
             offset + 3 + jump
         } else {
             offset + 3 - jump
         };
-        println!("{:-16} {:4} -> {}", name, offset, jump_to);
-        offset + 3
+        writeln!(out, "{:-16} {:4} -> {}", name, offset, jump_to)?;
+        Ok(offset + 3)
     }
 
     pub fn get_jump_offset(&self, offset: usize) -> usize {
@@ -216,22 +276,22 @@ impl Chunk {
     }
 
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    fn constant_instruction(&self, name: &str, offset: usize) -> usize {
+    fn constant_instruction(&self, out: &mut dyn Write, name: &str, offset: usize) -> io::Result<usize> {
         let constant = self.code[offset + 1];
-        print!("{:-16} {:4} '", name, constant);
-        self.constants.print_value(constant as usize);
-        println!("'");
-        offset + 2
+        write!(out, "{:-16} {:4} '", name, constant)?;
+        self.constants.write_value(out, constant as usize)?;
+        writeln!(out, "'")?;
+        Ok(offset + 2)
     }
     
      #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-     fn invoke_instruction(&self, name:&str, offset: usize) -> usize {
+     fn invoke_instruction(&self, out: &mut dyn Write, name: &str, offset: usize) -> io::Result<usize> {
         let constant = self.code[offset + 1];
         let arg_count = self.code[offset + 2];
-        print!("{:-16} ({} args) {:4} '", name, arg_count, constant);
-        self.constants.print_value(constant as usize);
-        println!("'");
-        offset + 3
+        write!(out, "{:-16} ({} args) {:4} '", name, arg_count, constant)?;
+        self.constants.write_value(out, constant as usize)?;
+        writeln!(out, "'")?;
+        Ok(offset + 3)
      }
 
 }
@@ -282,6 +342,20 @@ impl From<u8> for OpCode {
             34 => OpCode::Inherit,
             35 => OpCode::GetSuper,
             36 => OpCode::SuperInoke,
+            37 => OpCode::BuildList,
+            38 => OpCode::GetIndex,
+            39 => OpCode::SetIndex,
+            40 => OpCode::GetSlice,
+            41 => OpCode::BuildMap,
+            42 => OpCode::Modulo,
+            43 => OpCode::Power,
+            44 => OpCode::IntDivide,
+            45 => OpCode::BitAnd,
+            46 => OpCode::BitOr,
+            47 => OpCode::BitXor,
+            48 => OpCode::BitNot,
+            49 => OpCode::ShiftLeft,
+            50 => OpCode::ShiftRight,
             _ => unimplemented!("Invalid opcode"),
         }
     }
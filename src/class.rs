@@ -1,31 +1,48 @@
 use std::fmt::Display;
 use std::fmt::Result;
 use std::cell::RefCell;
-use std::ops::Deref;
-use std::rc::Rc;
 use std::collections::HashMap;
 
 use crate::value::*;
 use crate::closure::*;
+use crate::gc::*;
+use crate::string::*;
 
 
 
 #[derive(Debug)]
 pub struct Class{
-    name: String,   
-    methods: RefCell<HashMap<String, Rc<Closure>>>,
-    init: RefCell<Option<Rc<Closure>>>
+    name: Gc<Str>,
+    methods: RefCell<HashMap<Gc<Str>, Gc<Closure>>>,
+    init: RefCell<Option<Gc<Closure>>>
 }
 
 
 impl Display for Class {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result {
-        write!(f, "{}", self.name)
+        write!(f, "{}", self.name.get())
     }
 }
 
+impl Trace for Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.name);
+        for (name, method) in self.methods.borrow().iter() {
+            tracer.mark(*name);
+            tracer.mark(*method);
+        }
+        if let Some(init) = *self.init.borrow() {
+            tracer.mark(init);
+        }
+    }
+}
+
+impl HeapObject for Class {
+    const KIND: ObjectKind = ObjectKind::Class;
+}
+
 impl Class {
-    pub fn new(name:String) -> Self {
+    pub fn new(name: Gc<Str>) -> Self {
         Self {
             name,
             methods: RefCell::new(HashMap::new()),
@@ -33,27 +50,27 @@ impl Class {
         }
     }
 
-    pub fn set_init_method(&self, closure:Rc<Closure>) {      
+    pub(crate) fn set_init_method(&self, closure:Gc<Closure>) {      
         self.init.replace(Some(closure));
     }
 
-    pub fn get_init_method(&self) -> Option<Rc<Closure>> {
-        self.init.borrow().deref().clone()
+    pub fn get_init_method(&self) -> Option<Gc<Closure>> {
+        *self.init.borrow()
     }
 
-    pub fn add_method<T:Into<String>>(&self, name:T, value:&Value) {
+    pub(crate) fn add_method(&self, name: Gc<Str>, value:&Value) {
         if let Value::Closure(closure) = value{
-         self.methods.borrow_mut().insert(name.into(), Rc::clone(closure));
+         self.methods.borrow_mut().insert(name, *closure);
         }
     }
 
-    pub fn get_mehtod(&self, name:&str)-> Option<Rc<Closure>> {
-        self.methods.borrow().get(name).cloned()
+    pub fn get_mehtod(&self, name: Gc<Str>)-> Option<Gc<Closure>> {
+        self.methods.borrow().get(&name).cloned()
     }
 
     pub fn copy_method(&self, superclass:&Self) {
         for (k, v) in superclass.methods.borrow().iter() {
-            self.methods.borrow_mut().insert(k.to_string(), Rc::clone(v));
+            self.methods.borrow_mut().insert(*k, *v);
         }
     }
 }
\ No newline at end of file
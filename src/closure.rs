@@ -1,55 +1,58 @@
-use std::cell::RefCell;
 use std::fmt::{Display, Result};
 use std::rc::Rc;
 
 use crate::chunks::*;
 use crate::function::*;
+use crate::gc::*;
 use crate::upvalue::*;
-use crate::value::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Closure {
-    function: Rc<Function>,
-    upvalues: RefCell<Vec<Rc<Upvalue>>>,
+    function: Gc<Function>,
+    upvalues: Vec<Gc<Upvalue>>,
 }
 
 impl Display for Closure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result {
-        self.function.fmt(f)
+        self.function.get().fmt(f)
     }
 }
 
-impl Closure {
-    pub fn new(function: Rc<Function>) -> Self {
-        Self {
-            function: Rc::clone(&function),
-            upvalues: RefCell::new(Vec::new()),
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.function);
+        for upvalue in &self.upvalues {
+            tracer.mark(*upvalue);
         }
     }
+}
 
-    pub fn arity(&self) -> usize {
-        self.function.arity()
+impl HeapObject for Closure {
+    const KIND: ObjectKind = ObjectKind::Closure;
+
+    fn payload_size(&self) -> usize {
+        self.upvalues.capacity() * std::mem::size_of::<Gc<Upvalue>>()
     }
+}
 
-    pub fn get_chunk(&self) -> Rc<Chunk> {
-        self.function.get_chunk()
+impl Closure {
+    pub(crate) fn new(function: Gc<Function>, upvalues: Vec<Gc<Upvalue>>) -> Self {
+        Self { function, upvalues }
     }
 
-    pub fn stack_name(&self) -> &str {
-        self.function.stack_name()
+    pub fn arity(&self) -> usize {
+        self.function.get().arity()
     }
 
-    pub fn push_upvalue(&self, value: &Rc<RefCell<Value>>) {
-        self.upvalues
-            .borrow_mut()
-            .push(Rc::new(Upvalue::new(value)));
+    pub fn get_chunk(&self) -> Rc<Chunk> {
+        self.function.get().get_chunk()
     }
 
-    pub fn get_upvalue(&self, offset: usize) -> Rc<RefCell<Value>> {
-        self.upvalues.borrow()[offset].value()
+    pub fn stack_name(&self) -> &str {
+        self.function.get().stack_name()
     }
 
-    pub fn modify(&self, offset: usize, value: &Rc<RefCell<Value>>) {
-        self.upvalues.borrow_mut()[offset].set(value); // = Rc::new(Upvalue::new(value))
+    pub(crate) fn get_upvalue(&self, offset: usize) -> Gc<Upvalue> {
+        self.upvalues[offset]
     }
 }
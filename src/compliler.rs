@@ -2,19 +2,27 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::chunks::*;
-use crate::error::*;
+use crate::diagnostic::*;
 use crate::function::*;
+use crate::gc::*;
 use crate::scanner::*;
+use crate::span::*;
 use crate::token::*;
 use crate::token_type::*;
 use crate::value::*;
+use crate::vm::VM;
 
-pub struct Compiler {
-    rules: Vec<ParseRule>,
+pub struct Compiler<'a> {
+    rules: Vec<ParseRule<'a>>,
     parser: Parser,
     scanner: Scanner,
     result: RefCell<Rc<CompilerResult>>,
-    current_class: RefCell<Option<Rc<ClassCompiler>>>
+    current_class: RefCell<Option<Rc<ClassCompiler>>>,
+    // Span of the left operand while an infix rule runs.
+    infix_start: Span,
+    // The text being compiled, shared by every chunk made from it.
+    source: Rc<str>,
+    heap: &'a mut Heap,
 }
 
 #[derive(PartialEq, Default)]
@@ -57,6 +65,18 @@ struct CompilerResult {
     ctype: ChunkType,
     enclosing: RefCell<Option<Rc<CompilerResult>>>,
     upvalues: RefCell<Vec<UpvlaueData>>,   
+    loops: RefCell<Vec<LoopState>>,
+}
+
+/// A loop being compiled, for `break` and `continue` to jump out of.
+struct LoopState {
+    label: Option<String>,
+    // Where `continue` jumps to.
+    start: usize,
+    // Locals deeper than this belong to the body and are discarded by a jump.
+    scope_depth: usize,
+    // `break` jumps, patched once the end of the loop is known.
+    breaks: Vec<usize>,
 }
 
 enum FindResult {
@@ -72,7 +92,7 @@ impl CompilerResult {
         locals.borrow_mut().push( 
             if ctype != ChunkType::Function {
                   Local { 
-                    name: Token { ttype: TokenType::This, lexeme: String::from("this"), line: 0 }, 
+                    name: Token { ttype: TokenType::This, lexeme: String::from("this"), line: 0, span: Span::default() }, 
                     depth: Some(0), 
                     is_captured: false
                }
@@ -211,8 +231,8 @@ impl CompilerResult {
         self.locals.borrow_mut().push(local)
     }
 
-    fn write(&self, byte: u8, line: usize) {
-        self.chunk.borrow_mut().write(byte, line);
+    fn write(&self, byte: u8, span: Span) {
+        self.chunk.borrow_mut().write(byte, span);
     }
 
     fn count(&self) -> usize {
@@ -229,7 +249,10 @@ impl CompilerResult {
 
     #[cfg(feature = "debug_print_code")]
     fn disassemble<T: Into<String>>(&self, name: T) {
-        self.chunk.borrow().disassemble(name);
+        let _ = self
+            .chunk
+            .borrow()
+            .disassemble(&mut std::io::stdout().lock(), name);
     }
 }
 
@@ -239,12 +262,13 @@ pub struct Parser {
     previous: Token,
     had_error: RefCell<bool>,
     panic_mode: RefCell<bool>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
 }
 
 #[derive(Clone, Copy)]
-struct ParseRule {
-    prefix: Option<fn(&mut Compiler, bool)>,
-    infix: Option<fn(&mut Compiler, bool)>,
+struct ParseRule<'a> {
+    prefix: Option<fn(&mut Compiler<'a>, bool)>,
+    infix: Option<fn(&mut Compiler<'a>, bool)>,
     precedence: Precedence,
 }
 
@@ -256,9 +280,14 @@ enum Precedence {
     And,        // and
     Equality,   //  == !=
     Comparison, // < > <= =>
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
+    Shift,      // << >>
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / % ~/
     Unary,      // ! -
+    Exponent,   // **
     Call,       // . ()
     Primary,
 }
@@ -279,11 +308,16 @@ impl From<usize> for Precedence {
             3 => Precedence::And,
             4 => Precedence::Equality,
             5 => Precedence::Comparison,
-            6 => Precedence::Term,
-            7 => Precedence::Factor,
-            8 => Precedence::Unary,
-            9 => Precedence::Call,
-            10 => Precedence::Primary,
+            6 => Precedence::BitOr,
+            7 => Precedence::BitXor,
+            8 => Precedence::BitAnd,
+            9 => Precedence::Shift,
+            10 => Precedence::Term,
+            11 => Precedence::Factor,
+            12 => Precedence::Unary,
+            13 => Precedence::Exponent,
+            14 => Precedence::Call,
+            15 => Precedence::Primary,
             _ => panic!("Cannot covert {value} into precedence"),
         }
     }
@@ -299,8 +333,17 @@ impl Precedence {
     }
 }
 
-impl Compiler {
-    pub fn new() -> Self {
+impl<'a> Compiler<'a> {
+    /// Creates a compiler that allocates functions and strings on `vm`'s
+    /// heap.
+    ///
+    /// The compiled function is not rooted: pin it with [`VM::pin`] as
+    /// `Value::Func` before the VM next collects garbage.
+    pub fn new(vm: &'a mut VM) -> Self {
+        Self::with_heap(vm.heap_mut())
+    }
+
+    pub(crate) fn with_heap(heap: &'a mut Heap) -> Self {
         let mut rules = vec![
             ParseRule {
                 prefix: None,
@@ -335,11 +378,27 @@ impl Compiler {
             infix: Some(|c, b| c.binary(b)),
             precedence: Precedence::Factor,
         };
+        rules[TokenType::Percent as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::Factor,
+        };
+        rules[TokenType::TildeSlash as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::Factor,
+        };
+        rules[TokenType::StarStar as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::Exponent,
+        };
         rules[TokenType::Number as usize].prefix = Some(|c, b| c.number(b));
         rules[TokenType::Nil as usize].prefix = Some(|c, b| c.literal(b));
         rules[TokenType::True as usize].prefix = Some(|c, b| c.literal(b));
         rules[TokenType::False as usize].prefix = Some(|c, b| c.literal(b));
         rules[TokenType::Bang as usize].prefix = Some(|c, b| c.unary(b));
+        rules[TokenType::Tilde as usize].prefix = Some(|c, b| c.unary(b));
 
         rules[TokenType::BangEqual as usize] = ParseRule {
             prefix: None,
@@ -373,6 +432,31 @@ impl Compiler {
             infix: Some(|c, b| c.binary(b)),
             precedence: Precedence::Comparison,
         };
+        rules[TokenType::Pipe as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::BitOr,
+        };
+        rules[TokenType::Caret as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::BitXor,
+        };
+        rules[TokenType::Ampersand as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::BitAnd,
+        };
+        rules[TokenType::LessLess as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::Shift,
+        };
+        rules[TokenType::GreaterGreater as usize] = ParseRule {
+            prefix: None,
+            infix: Some(|c, b| c.binary(b)),
+            precedence: Precedence::Shift,
+        };
         rules[TokenType::String as usize].prefix = Some(|c, b| c.string(b));
         rules[TokenType::Identifier as usize].prefix = Some(|c, b| c.variable(b));
         rules[TokenType::And as usize] = ParseRule {
@@ -390,6 +474,12 @@ impl Compiler {
             infix: Some(Compiler::dot),
             precedence: Precedence::Call,
         };
+        rules[TokenType::LeftBracket as usize] = ParseRule {
+            prefix: Some(Compiler::list),
+            infix: Some(Compiler::subscript),
+            precedence: Precedence::Call,
+        };
+        rules[TokenType::LeftBrace as usize].prefix = Some(Compiler::map);
         rules[TokenType::This as usize].prefix = Some(Compiler::this);
         rules[TokenType::Super as usize].prefix = Some(Compiler::super_);
 
@@ -398,17 +488,25 @@ impl Compiler {
             parser: Parser::default(),
             scanner: Scanner::new(""),
             result: RefCell::new(Rc::new(CompilerResult::default())),
-            current_class: RefCell::new(None)
+            current_class: RefCell::new(None),
+            infix_start: Span::default(),
+            source: Rc::default(),
+            heap,
         }
     }
 
-    pub fn compile(&mut self, source: &str) -> Result<Function, InterpretResult> {
+    /// Compiles `source` into the top-level script function.
+    ///
+    /// On failure every diagnostic found is returned, in source order; the
+    /// parser resynchronizes at statement boundaries after each error.
+    pub fn compile(&mut self, source: &str) -> Result<Gc<Function>, Vec<Diagnostic>> {
         self.result.borrow().push(Local {
             name: Token::default(),
             depth: Some(0),
             is_captured: false,
         });
         self.scanner = Scanner::new(source);
+        self.source = Rc::from(source);
         self.advance();
 
         while !self.is_match(TokenType::Eof) {
@@ -418,11 +516,12 @@ impl Compiler {
         self.end_compiler();
 
         if *self.parser.had_error.borrow() {
-            Err(InterpretResult::CompileError)
+            Err(self.parser.diagnostics.take())
         } else {
             let result = self.result.replace(Rc::new(CompilerResult::default()));
-            let chunk = result.chunk.replace(Chunk::new());
-            Ok(Function::toplevel(&Rc::new(chunk)))
+            let mut chunk = result.chunk.replace(Chunk::new());
+            chunk.set_source(Rc::clone(&self.source));
+            Ok(self.heap.alloc(Function::toplevel(&Rc::new(chunk))))
         }
     }
 
@@ -435,7 +534,7 @@ impl Compiler {
                 break;
             }
             let message = self.parser.current.lexeme.as_str();
-            self.error_at_current(message);
+            self.error_at_current(ErrorCode::InvalidToken, message);
         }
     }
 
@@ -444,7 +543,7 @@ impl Compiler {
             self.advance();
             return;
         }
-        self.error_at_current(message);
+        self.error_at_current(ErrorCode::UnexpectedToken, message);
     }
 
     fn check(&self, ttype: TokenType) -> bool {
@@ -461,9 +560,11 @@ impl Compiler {
     }
 
     fn emit_byte<T: Into<u8>>(&mut self, byte: T) {
-        self.result
-            .borrow()
-            .write(byte.into(), self.parser.previous.line);
+        self.emit_byte_at(byte, self.parser.previous.span);
+    }
+
+    fn emit_byte_at<T: Into<u8>>(&mut self, byte: T, span: Span) {
+        self.result.borrow().write(byte.into(), span);
     }
 
     fn emit_bytes<T: Into<u8>, U: Into<u8>>(&mut self, byte1: T, byte2: U) {
@@ -471,12 +572,17 @@ impl Compiler {
         self.emit_byte(byte2);
     }
 
+    fn emit_bytes_at<T: Into<u8>, U: Into<u8>>(&mut self, byte1: T, byte2: U, span: Span) {
+        self.emit_byte_at(byte1, span);
+        self.emit_byte_at(byte2, span);
+    }
+
     fn emit_loop(&mut self, loop_start: usize) {
         self.emit_byte(OpCode::Loop);
 
         let offset = self.result.borrow().count() + 2 - loop_start;
         if offset > u16::MAX as usize {
-            self.error("Loop body too large.");
+            self.error(ErrorCode::LoopTooLarge, "Loop body too large.");
         }
 
         self.emit_byte(((offset >> 8) & 0xff) as u8);
@@ -503,7 +609,7 @@ impl Compiler {
         if let Some(constant) = self.result.borrow().add_constant(value) {
             constant
         } else {
-            self.error("Too many constants in one chunk");
+            self.error(ErrorCode::TooManyConstants, "Too many constants in one chunk");
             0
         }
     }
@@ -516,7 +622,7 @@ impl Compiler {
     fn patch_jump(&mut self, offset: usize) {
         let jump = self.result.borrow().count() - offset - 2;
         if jump > u16::MAX as usize {
-            self.error("Too mutch code to jump over.");
+            self.error(ErrorCode::JumpTooLarge, "Too mutch code to jump over.");
         }
 
         self.result
@@ -560,47 +666,142 @@ impl Compiler {
     }
 
     fn binary(&mut self, _can_assign: bool) {
+        let start = self.infix_start;
         let operator_type = self.parser.previous.ttype;
         //let rule = self.get_rule(operator_type);
         let rule = &self.rules[operator_type as usize];
 
-        self.parse_precedence(rule.precedence.next());
+        // `**` is right-associative, so its right operand may contain
+        // another `**`.
+        if operator_type == TokenType::StarStar {
+            self.parse_precedence(rule.precedence);
+        } else {
+            self.parse_precedence(rule.precedence.next());
+        }
 
+        let span = start.to(self.parser.previous.span);
         match operator_type {
-            TokenType::Plus => self.emit_byte(OpCode::Add),
-            TokenType::Minus => self.emit_byte(OpCode::Subtract),
-            TokenType::Star => self.emit_byte(OpCode::Multiply),
-            TokenType::Slash => self.emit_byte(OpCode::Divide),
-            TokenType::BangEqual => self.emit_bytes(OpCode::Equal, OpCode::Not),
-            TokenType::Equal => self.emit_byte(OpCode::Equal),
-            TokenType::Greater => self.emit_byte(OpCode::Greater),
-            TokenType::GreaterEqual => self.emit_bytes(OpCode::Less, OpCode::Not),
-            TokenType::Less => self.emit_byte(OpCode::Less),
-            TokenType::LessEqual => self.emit_bytes(OpCode::Greater, OpCode::Not),
+            TokenType::Plus => self.emit_byte_at(OpCode::Add, span),
+            TokenType::Minus => self.emit_byte_at(OpCode::Subtract, span),
+            TokenType::Star => self.emit_byte_at(OpCode::Multiply, span),
+            TokenType::Slash => self.emit_byte_at(OpCode::Divide, span),
+            TokenType::Percent => self.emit_byte_at(OpCode::Modulo, span),
+            TokenType::TildeSlash => self.emit_byte_at(OpCode::IntDivide, span),
+            TokenType::StarStar => self.emit_byte_at(OpCode::Power, span),
+            TokenType::Ampersand => self.emit_byte_at(OpCode::BitAnd, span),
+            TokenType::Pipe => self.emit_byte_at(OpCode::BitOr, span),
+            TokenType::Caret => self.emit_byte_at(OpCode::BitXor, span),
+            TokenType::LessLess => self.emit_byte_at(OpCode::ShiftLeft, span),
+            TokenType::GreaterGreater => self.emit_byte_at(OpCode::ShiftRight, span),
+            TokenType::BangEqual => self.emit_bytes_at(OpCode::Equal, OpCode::Not, span),
+            TokenType::Equal => self.emit_byte_at(OpCode::Equal, span),
+            TokenType::Greater => self.emit_byte_at(OpCode::Greater, span),
+            TokenType::GreaterEqual => self.emit_bytes_at(OpCode::Less, OpCode::Not, span),
+            TokenType::Less => self.emit_byte_at(OpCode::Less, span),
+            TokenType::LessEqual => self.emit_bytes_at(OpCode::Greater, OpCode::Not, span),
 
             _ => todo!(),
         }
     }
 
     fn call(&mut self, _can_assign: bool) {
+        let start = self.infix_start;
         let arg_count = self.argument_list();
-        self.emit_bytes(OpCode::Call, arg_count);
+        let span = start.to(self.parser.previous.span);
+        self.emit_bytes_at(OpCode::Call, arg_count, span);
     }
 
     fn dot(&mut self, can_assign:bool) {
+        let start = self.infix_start;
         self.consume(TokenType::Identifier, "Expect property name after '.'");
         let name = self.identifier_constant(&self.parser.previous.clone());
 
         if can_assign && self.is_match(TokenType::Assign) {
             self.expression();
-            self.emit_bytes(OpCode::SetProperty, name);            
+            let span = start.to(self.parser.previous.span);
+            self.emit_bytes_at(OpCode::SetProperty, name, span);            
         } else if self.is_match(TokenType::LeftParen) {
             let arg_count = self.argument_list();
-            self.emit_bytes(OpCode::Invoke, name);
-            self.emit_byte(arg_count);
+            let span = start.to(self.parser.previous.span);
+            self.emit_bytes_at(OpCode::Invoke, name, span);
+            self.emit_byte_at(arg_count, span);
         }
         else {
-            self.emit_bytes(OpCode::GetProperty, name);
+            let span = start.to(self.parser.previous.span);
+            self.emit_bytes_at(OpCode::GetProperty, name, span);
+        }
+    }
+
+    fn list(&mut self, _can_assign: bool) {
+        let start = self.parser.previous.span;
+        let mut count = 0;
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                self.expression();
+                if count == 255 {
+                    self.error(ErrorCode::TooManyElements, "Can't have more than 255 elements in a list literal.");
+                }
+                count += 1;
+                if !self.is_match(TokenType::Comma) || self.check(TokenType::RightBracket) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.");
+        let span = start.to(self.parser.previous.span);
+        self.emit_bytes_at(OpCode::BuildList, count as u8, span);
+    }
+
+    fn map(&mut self, _can_assign: bool) {
+        let start = self.parser.previous.span;
+        let mut count = 0;
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                self.expression();
+                self.consume(TokenType::Colon, "Expect ':' after map key.");
+                self.expression();
+                if count == 255 {
+                    self.error(ErrorCode::TooManyEntries, "Can't have more than 255 entries in a map literal.");
+                }
+                count += 1;
+                if !self.is_match(TokenType::Comma) || self.check(TokenType::RightBrace) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+        let span = start.to(self.parser.previous.span);
+        self.emit_bytes_at(OpCode::BuildMap, count as u8, span);
+    }
+
+    fn subscript(&mut self, can_assign: bool) {
+        let start = self.infix_start;
+        let is_slice = if self.is_match(TokenType::Colon) {
+            self.emit_byte(OpCode::Nil);
+            true
+        } else {
+            self.expression();
+            self.is_match(TokenType::Colon)
+        };
+        if is_slice {
+            if self.check(TokenType::RightBracket) {
+                self.emit_byte(OpCode::Nil);
+            } else {
+                self.expression();
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+        if is_slice {
+            let span = start.to(self.parser.previous.span);
+            self.emit_byte_at(OpCode::GetSlice, span);
+        } else if can_assign && self.is_match(TokenType::Assign) {
+            self.expression();
+            let span = start.to(self.parser.previous.span);
+            self.emit_byte_at(OpCode::SetIndex, span);
+        } else {
+            let span = start.to(self.parser.previous.span);
+            self.emit_byte_at(OpCode::GetIndex, span);
         }
     }
 
@@ -637,14 +838,17 @@ impl Compiler {
 
     fn string(&mut self, _can_assign: bool) {
         let len = self.parser.previous.lexeme.len() - 1;
-        let string = self.parser.previous.lexeme[1..len].to_string();
+        let string = self.heap.intern(&self.parser.previous.lexeme[1..len]);
         self.emit_constant(Value::Str(string));
     }
 
     fn resolve_local(&self, name: &Token) -> Option<u8> {
         match self.result.borrow().resolve_local(name) {
             Err(FindResult::Uninitialized) => {
-                self.error("Cannot read local variable in its own initializer.");
+                self.error(
+                    ErrorCode::ReadInOwnInitializer,
+                    "Cannot read local variable in its own initializer.",
+                );
                 None
             }
             Ok(val) => val,
@@ -655,7 +859,10 @@ impl Compiler {
     fn resolve_upvalue(&self, name: &Token) -> Option<u8> {
         match self.result.borrow().resolve_upvalue(name) {
             Err(FindResult::ToManyvariables) => {
-                self.error("TODO - error message");
+                self.error(
+                    ErrorCode::TooManyUpvalues,
+                    "Too many closure variables in function.",
+                );
                 None
             }
             Ok(val) => val,
@@ -691,7 +898,7 @@ impl Compiler {
 
     fn this(&mut self, _can_assign: bool) {
         if self.current_class.borrow().is_none() {
-            self.error("Can't use 'this' outside of a class");
+            self.error(ErrorCode::ThisOutsideClass, "Can't use 'this' outside of a class");
         }
         
         self.variable(false);
@@ -699,10 +906,13 @@ impl Compiler {
 
     fn super_(&mut self, _can_assign:bool) {
         match self.current_class.borrow().as_ref() {
-            None => self.error("Can't use 'super' outside of a class"),
+            None => self.error(ErrorCode::SuperOutsideClass, "Can't use 'super' outside of a class"),
             Some(cc) => {
                 if !*cc.has_superclass.borrow() {
-                    self.error("Can't use 'super' in a class with no superclass.");
+                    self.error(
+                        ErrorCode::SuperWithoutSuperclass,
+                        "Can't use 'super' in a class with no superclass.",
+                    );
                 }
             }
         }
@@ -725,43 +935,49 @@ impl Compiler {
     }
 
     fn unary(&mut self, _can_assign: bool) {
+        let start = self.parser.previous.span;
         let operator_type = self.parser.previous.ttype;
 
         self.parse_precedence(Precedence::Unary);
 
+        let span = start.to(self.parser.previous.span);
         match operator_type {
-            TokenType::Minus => self.emit_byte(OpCode::Negate),
-            TokenType::Bang => self.emit_byte(OpCode::Not),
+            TokenType::Minus => self.emit_byte_at(OpCode::Negate, span),
+            TokenType::Bang => self.emit_byte_at(OpCode::Not, span),
+            TokenType::Tilde => self.emit_byte_at(OpCode::BitNot, span),
             _ => unimplemented!("nope"),
         }
     }
 
     fn parse_precedence(&mut self, precedence: Precedence) {
         self.advance();
+        let start = self.parser.previous.span;
         if let Some(prefix_rule) = self.rules[self.parser.previous.ttype as usize].prefix {
             let can_assign = precedence <= Precedence::Assignment;
             prefix_rule(self, can_assign);
             while precedence <= self.rules[self.parser.current.ttype as usize].precedence {
                 self.advance();
                 if let Some(infix_rule) = self.rules[self.parser.previous.ttype as usize].infix {
+                    self.infix_start = start;
                     infix_rule(self, can_assign);
                 }
                 if can_assign && self.is_match(TokenType::Assign) {
-                    self.error("Invalid assigment target");
+                    self.error(ErrorCode::InvalidAssignmentTarget, "Invalid assigment target");
                 }
             }
         } else {
-            self.error("Expect Expression.");
+            self.error(ErrorCode::ExpectExpression, "Expect Expression.");
         }
     }
 
     fn identifier_constant(&mut self, name: &Token) -> u8 {
-        self.make_costant(Value::Str(name.lexeme.clone()))
+        let name = self.heap.intern(&name.lexeme);
+        self.make_costant(Value::Str(name))
     }
 
     fn add_local(&mut self, name: &Token) {
         if self.result.borrow().locals() >= u8::MAX as usize {
-            self.error("Too many local variables in function.");
+            self.error(ErrorCode::TooManyLocals, "Too many local variables in function.");
             return;
         }
         self.result.borrow().push(Local {
@@ -775,7 +991,10 @@ impl Compiler {
         if self.result.borrow().in_scope() {
             let name = &self.parser.previous.lexeme;
             if let FindResult::Depth(_) = self.result.borrow().find_variable(name) {
-                self.error("Already a variable with this name in this scope.");
+                self.error(
+                    ErrorCode::DuplicateVariable,
+                    "Already a variable with this name in this scope.",
+                );
             } else {
                 self.add_local(&self.parser.previous.clone())
             }
@@ -813,7 +1032,7 @@ impl Compiler {
             loop {
                 self.expression();
                 if arg_count == 255 {
-                    self.error("Can't have more than 255 arguments.");
+                    self.error(ErrorCode::TooManyArguments, "Can't have more than 255 arguments.");
                 }
                 arg_count += 1;
                 if !self.is_match(TokenType::Comma) {
@@ -877,7 +1096,7 @@ impl Compiler {
         if !self.check(TokenType::RightParen) {
             loop {
                 if self.result.borrow().inc_arity() > 255 {
-                    self.error("Can't have more than 255 parameters.");
+                    self.error(ErrorCode::TooManyParameters, "Can't have more than 255 parameters.");
                 }
                 let constant = self.parse_variable("Expect parameter name.");
                 self.define_variable(constant);
@@ -897,7 +1116,8 @@ impl Compiler {
         let result = self.result.replace(prev_complier);
 
         if !*self.parser.had_error.borrow() {
-            let chunk = result.chunk.replace(Chunk::new());
+            let mut chunk = result.chunk.replace(Chunk::new());
+            chunk.set_source(Rc::clone(&self.source));
             let func = Function::new(
                 &*result.current_function.borrow(),
                 arity,
@@ -905,7 +1125,8 @@ impl Compiler {
                 result.upvalues.borrow().len(),
             );
 
-            let constant = self.make_costant(Value::Func(Rc::new(func)));
+            let func = self.heap.alloc(func);
+            let constant = self.make_costant(Value::Func(func));
             self.emit_bytes(OpCode::Closure, constant);
 
             for upvalue in result.upvalues.borrow().iter() {
@@ -954,7 +1175,7 @@ impl Compiler {
             self.variable(false);
             let prev = self.parser.previous.clone();
             if class_name.lexeme == prev.lexeme {
-                self.error("A class can't inherit from itself");
+                self.error(ErrorCode::InheritFromSelf, "A class can't inherit from itself");
             }
             self.begin_scope();
             self.add_local(&Token::new("super"));
@@ -1007,13 +1228,115 @@ impl Compiler {
         self.define_variable(global);
     }
 
+    fn begin_loop(&mut self, label: Option<String>, start: usize) {
+        let result = self.result.borrow();
+        let scope_depth = *result.scope_depth.borrow();
+        result.loops.borrow_mut().push(LoopState {
+            label,
+            start,
+            scope_depth,
+            breaks: Vec::new(),
+        });
+    }
+
+    fn end_loop(&mut self) {
+        let state = self.result.borrow().loops.borrow_mut().pop().unwrap();
+        for jump in state.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// Parses the optional label after `break` or `continue` and returns the
+    /// index of the loop it refers to.
+    fn target_loop(&mut self, code: ErrorCode, message: &str) -> Option<usize> {
+        let label = if self.is_match(TokenType::Identifier) {
+            Some(self.parser.previous.lexeme.clone())
+        } else {
+            None
+        };
+        let result = Rc::clone(&self.result.borrow());
+        let loops = result.loops.borrow();
+        if loops.is_empty() {
+            self.error(code, message);
+            return None;
+        }
+        let index = loops
+            .iter()
+            .rposition(|state| label.is_none() || state.label == label);
+        if index.is_none() {
+            self.error(
+                ErrorCode::UndefinedLabel,
+                &format!("No enclosing loop labeled '{}'.", label.unwrap()),
+            );
+        }
+        index
+    }
+
+    /// Emits code discarding the locals of the loop at `index` and of any
+    /// scopes inside it, without forgetting them at compile time.
+    fn discard_loop_locals(&mut self, index: usize) {
+        let result = Rc::clone(&self.result.borrow());
+        let scope_depth = result.loops.borrow()[index].scope_depth;
+        let count = result
+            .locals
+            .borrow()
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_some_and(|depth| depth > scope_depth))
+            .count();
+        // A local captured further down the body may already have an open
+        // upvalue from an earlier iteration, so close them all.
+        for _ in 0..count {
+            self.emit_byte(OpCode::CloseUpvalue);
+        }
+    }
+
+    fn break_statement(&mut self) {
+        let target = self.target_loop(
+            ErrorCode::BreakOutsideLoop,
+            "Can't use 'break' outside of a loop.",
+        );
+        self.consume(TokenType::SemiColon, "Expect ';' after 'break'.");
+        if let Some(index) = target {
+            self.discard_loop_locals(index);
+            let jump = self.emit_jump(OpCode::Jump);
+            self.result.borrow().loops.borrow_mut()[index].breaks.push(jump);
+        }
+    }
+
+    fn continue_statement(&mut self) {
+        let target = self.target_loop(
+            ErrorCode::ContinueOutsideLoop,
+            "Can't use 'continue' outside of a loop.",
+        );
+        self.consume(TokenType::SemiColon, "Expect ';' after 'continue'.");
+        if let Some(index) = target {
+            self.discard_loop_locals(index);
+            let start = self.result.borrow().loops.borrow()[index].start;
+            self.emit_loop(start);
+        }
+    }
+
+    fn labeled_statement(&mut self) {
+        self.advance();
+        let label = self.parser.previous.lexeme.clone();
+        self.consume(TokenType::Colon, "Expect ':' after label.");
+        if self.is_match(TokenType::While) {
+            self.while_statment(Some(label));
+        } else if self.is_match(TokenType::For) {
+            self.for_statement(Some(label));
+        } else {
+            self.error_at_current(ErrorCode::UnexpectedToken, "Expect a loop after label.");
+        }
+    }
+
     fn expression_statement(&mut self) {
         self.expression();
         self.consume(TokenType::SemiColon, "Expect ';' after expression.");
         self.emit_byte(OpCode::Pop);
     }
 
-    fn for_statement(&mut self) {
+    fn for_statement(&mut self, label: Option<String>) {
         self.begin_scope();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
         if self.is_match(TokenType::SemiColon) {
@@ -1048,12 +1371,14 @@ impl Compiler {
             self.patch_jump(body_jump);
         }
 
+        self.begin_loop(label, loop_start);
         self.statement();
         self.emit_loop(loop_start);
         if let Some(exit) = exit_jump {
             self.patch_jump(exit);
             self.emit_byte(OpCode::Pop);
         }
+        self.end_loop();
         self.end_scope();
     }
 
@@ -1065,13 +1390,16 @@ impl Compiler {
 
     fn return_statement(&mut self) {
         if self.result.borrow().ctype == ChunkType::Script {
-            self.error("Can't return from top-level code.");
+            self.error(ErrorCode::ReturnFromTopLevel, "Can't return from top-level code.");
         }
         if self.is_match(TokenType::SemiColon) {
             self.emit_return();
         } else {
             if self.result.borrow().ctype == ChunkType::Initializer {
-                self.error("Can't return a value form a initializer.");
+                self.error(
+                    ErrorCode::ReturnFromInitializer,
+                    "Can't return a value form a initializer.",
+                );
             }
             self.expression();
             self.consume(TokenType::SemiColon, "Expect ';' after return value");
@@ -1079,7 +1407,7 @@ impl Compiler {
         }
     }
 
-    fn while_statment(&mut self) {
+    fn while_statment(&mut self, label: Option<String>) {
         let loop_start = self.result.borrow().count();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
         self.expression();
@@ -1087,11 +1415,13 @@ impl Compiler {
 
         let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
         self.emit_byte(OpCode::Pop);
+        self.begin_loop(label, loop_start);
         self.statement();
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump);
         self.emit_byte(OpCode::Pop);
+        self.end_loop();
     }
 
     fn synchronize(&mut self) {
@@ -1110,6 +1440,8 @@ impl Compiler {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::Break
+                | TokenType::Continue
                 | TokenType::Return => return,
                 _ => {}
             }
@@ -1138,13 +1470,19 @@ impl Compiler {
         if self.is_match(TokenType::Print) {
             self.print_statement();
         } else if self.is_match(TokenType::For) {
-            self.for_statement()
+            self.for_statement(None)
         } else if self.is_match(TokenType::If) {
             self.if_statement();
         } else if self.is_match(TokenType::Return) {
             self.return_statement();
         } else if self.is_match(TokenType::While) {
-            self.while_statment();
+            self.while_statment(None);
+        } else if self.is_match(TokenType::Break) {
+            self.break_statement();
+        } else if self.is_match(TokenType::Continue) {
+            self.continue_statement();
+        } else if self.check(TokenType::Identifier) && self.scanner.peek_token() == TokenType::Colon {
+            self.labeled_statement();
         } else if self.is_match(TokenType::LeftBrace) {
             self.begin_scope();
             self.block();
@@ -1154,29 +1492,34 @@ impl Compiler {
         }
     }
 
-    fn error_at_current(&self, message: &str) {
-        self.error_at(&self.parser.current, message)
+    fn error_at_current(&self, code: ErrorCode, message: &str) {
+        self.error_at(&self.parser.current, code, message)
     }
 
-    fn error(&self, message: &str) {
-        self.error_at(&self.parser.previous, message);
+    fn error(&self, code: ErrorCode, message: &str) {
+        self.error_at(&self.parser.previous, code, message);
     }
 
-    fn error_at(&self, token: &Token, message: &str) {
+    fn error_at(&self, token: &Token, code: ErrorCode, message: &str) {
         if *self.parser.panic_mode.borrow() {
             return;
         }
         self.parser.panic_mode.replace(true);
-        eprint!("[line {:}] Error", token.line);
 
-        if token.ttype == TokenType::Eof {
-            eprint!(" at end");
-        } else if token.ttype == TokenType::Error {
-        } else {
-            eprint!(" at '{}'", token.lexeme);
-        }
+        let site = match token.ttype {
+            TokenType::Eof => ErrorSite::End,
+            TokenType::Error => ErrorSite::Source,
+            _ => ErrorSite::Token(token.lexeme.clone()),
+        };
 
-        eprintln!(": {}", message);
+        self.parser.diagnostics.borrow_mut().push(Diagnostic {
+            severity: Severity::Error,
+            line: token.line,
+            span: token.span,
+            site,
+            message: message.to_string(),
+            code,
+        });
         self.parser.had_error.replace(true);
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt::{Display, Formatter, Result};
+
+use crate::span::*;
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Stable identifier for each kind of compile diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    InvalidToken,
+    UnexpectedToken,
+    ExpectExpression,
+    InvalidAssignmentTarget,
+    TooManyConstants,
+    TooManyLocals,
+    TooManyUpvalues,
+    TooManyArguments,
+    TooManyParameters,
+    JumpTooLarge,
+    LoopTooLarge,
+    DuplicateVariable,
+    ReadInOwnInitializer,
+    ReturnFromTopLevel,
+    ReturnFromInitializer,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    InheritFromSelf,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    UndefinedLabel,
+    TooManyElements,
+    TooManyEntries,
+}
+
+impl ErrorCode {
+    /// The short code shown to users, e.g. `E0003`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidToken => "E0001",
+            ErrorCode::UnexpectedToken => "E0002",
+            ErrorCode::ExpectExpression => "E0003",
+            ErrorCode::InvalidAssignmentTarget => "E0004",
+            ErrorCode::TooManyConstants => "E0005",
+            ErrorCode::TooManyLocals => "E0006",
+            ErrorCode::TooManyUpvalues => "E0007",
+            ErrorCode::TooManyArguments => "E0008",
+            ErrorCode::TooManyParameters => "E0009",
+            ErrorCode::JumpTooLarge => "E0010",
+            ErrorCode::LoopTooLarge => "E0011",
+            ErrorCode::DuplicateVariable => "E0012",
+            ErrorCode::ReadInOwnInitializer => "E0013",
+            ErrorCode::ReturnFromTopLevel => "E0014",
+            ErrorCode::ReturnFromInitializer => "E0015",
+            ErrorCode::ThisOutsideClass => "E0016",
+            ErrorCode::SuperOutsideClass => "E0017",
+            ErrorCode::SuperWithoutSuperclass => "E0018",
+            ErrorCode::InheritFromSelf => "E0019",
+            ErrorCode::BreakOutsideLoop => "E0020",
+            ErrorCode::ContinueOutsideLoop => "E0021",
+            ErrorCode::UndefinedLabel => "E0022",
+            ErrorCode::TooManyElements => "E0023",
+            ErrorCode::TooManyEntries => "E0024",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Where in the token stream a diagnostic was reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorSite {
+    /// At a token with this lexeme.
+    Token(String),
+    /// At the end of the source.
+    End,
+    /// Inside a token the scanner could not produce.
+    Source,
+}
+
+/// A problem found while compiling Lox source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    /// Source range of the offending token.
+    pub span: Span,
+    pub site: ErrorSite,
+    pub message: String,
+    pub code: ErrorCode,
+}
+
+impl Diagnostic {
+    /// The lexeme of the offending token, if there was one.
+    pub fn lexeme(&self) -> Option<&str> {
+        match &self.site {
+            ErrorSite::Token(lexeme) => Some(lexeme.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        write!(f, "[line {}] {severity}", self.line)?;
+        match &self.site {
+            ErrorSite::Token(lexeme) => write!(f, " at '{lexeme}'")?,
+            ErrorSite::End => write!(f, " at end")?,
+            ErrorSite::Source => {}
+        }
+        write!(f, ": {}", self.message)
+    }
+}
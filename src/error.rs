@@ -1,5 +1,113 @@
+use std::fmt::{Display, Formatter, Result};
+use std::rc::Rc;
+
+use crate::diagnostic::*;
+use crate::span::*;
+
+/// Outcome of a failed [`VM::interpret`](crate::VM::interpret) call.
+#[derive(Debug, PartialEq)]
 pub enum InterpretResult {
-    _Ok,
-    CompileError,
-    RuntimeError,
+    /// The source did not compile.
+    CompileError(Vec<Diagnostic>),
+    /// The script failed while running.
+    RuntimeError(RuntimeError),
 }
+
+/// Broad category of a [`RuntimeError`], for hosts that want to react to
+/// some failures differently from others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuntimeErrorKind {
+    /// An operand or receiver had the wrong type.
+    Type,
+    UndefinedVariable,
+    UndefinedProperty,
+    /// A call passed the wrong number of arguments.
+    Arity,
+    /// Something other than a function or class was called.
+    NotCallable,
+    /// A list index was out of bounds.
+    Index,
+    /// A map had no entry for the key looked up.
+    Key,
+    /// A number was outside the range an operator accepts, such as a
+    /// shift count outside `0..=63`.
+    Range,
+    /// Calls nested deeper than [`Limits::max_frames`](crate::Limits::max_frames).
+    StackOverflow,
+    /// The value stack grew past [`Limits::max_stack`](crate::Limits::max_stack).
+    StackLimit,
+    /// More instructions ran than [`Limits::max_instructions`](crate::Limits::max_instructions)
+    /// allows.
+    InstructionLimit,
+    /// The script ran past [`Limits::timeout`](crate::Limits::timeout).
+    Timeout,
+    /// Memory use went past [`Limits::max_memory`](crate::Limits::max_memory)
+    /// and collecting garbage did not free enough.
+    OutOfMemory,
+    /// The host stopped the script through an
+    /// [`InterruptHandle`](crate::InterruptHandle).
+    Interrupted,
+    /// The host passed in, or a native returned, a handle to an object
+    /// that has been collected or belongs to another VM.
+    InvalidHandle,
+    /// Program output could not be written.
+    Io,
+    /// A native function reported a failure.
+    Native,
+}
+
+/// One active call at the moment a runtime error was raised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Name of the function, or `script` for top-level code.
+    pub function: String,
+    pub line: usize,
+    /// Offset of the failing instruction within the function's chunk.
+    pub offset: usize,
+    /// Source range the failing instruction was compiled from.
+    pub span: Span,
+    /// The source text `span` points into, or `None` for natives.
+    pub source: Option<Rc<str>>,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "[line {}] in {}", self.line, self.function)
+    }
+}
+
+/// An error raised while running a script, with the call stack at the
+/// point of failure. The innermost frame comes first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub kind: RuntimeErrorKind,
+    pub trace: Vec<StackFrame>,
+}
+
+impl RuntimeError {
+    pub fn new<T: Into<String>>(kind: RuntimeErrorKind, message: T) -> Self {
+        Self {
+            message: message.into(),
+            kind,
+            trace: Vec::new(),
+        }
+    }
+
+    /// The line the error was raised on, if any frame was active.
+    pub fn line(&self) -> Option<usize> {
+        self.trace.first().map(|frame| frame.line)
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.trace {
+            write!(f, "\n{frame}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,151 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::rc::Rc;
+
+use crate::error::*;
+use crate::gc::*;
+use crate::native::*;
+use crate::value::*;
+use crate::vm::*;
+
+type ForeignMethodBody = dyn Fn(&mut VM, Gc<Foreign>, Args) -> Result<Value, RuntimeError>;
+
+struct ForeignMethod {
+    arity: Arity,
+    body: Box<ForeignMethodBody>,
+}
+
+/// A class defined by the host whose methods are implemented in Rust.
+///
+/// Scripts cannot construct foreign objects themselves; the host creates
+/// them with [`VM::new_foreign`](crate::VM::new_foreign) and hands them
+/// over as globals, arguments or native return values.
+pub struct ForeignClass {
+    name: String,
+    methods: RefCell<HashMap<String, Rc<ForeignMethod>>>,
+}
+
+impl ForeignClass {
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds a method. `arity` does not count the receiver, which is passed
+    /// to `body` separately.
+    pub fn add_method<T, F>(&self, name: T, arity: Arity, body: F)
+    where
+        T: Into<String>,
+        F: Fn(&mut VM, Gc<Foreign>, Args) -> Result<Value, RuntimeError> + 'static,
+    {
+        self.methods.borrow_mut().insert(
+            name.into(),
+            Rc::new(ForeignMethod {
+                arity,
+                body: Box::new(body),
+            }),
+        );
+    }
+
+    pub fn has_method(&self, name: &str) -> bool {
+        self.methods.borrow().contains_key(name)
+    }
+}
+
+impl Debug for ForeignClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<foreign class {}>", self.name)
+    }
+}
+
+/// Host data exposed to scripts as an object of a [`ForeignClass`].
+///
+/// Create one with [`VM::new_foreign`]. The data must not hold Lox values,
+/// as the garbage collector cannot see them.
+pub struct Foreign {
+    class: Rc<ForeignClass>,
+    data: RefCell<Box<dyn Any>>,
+}
+
+impl Foreign {
+    pub(crate) fn new<T: Any>(class: &Rc<ForeignClass>, data: T) -> Self {
+        Self {
+            class: Rc::clone(class),
+            data: RefCell::new(Box::new(data)),
+        }
+    }
+
+    pub fn get_class(&self) -> Rc<ForeignClass> {
+        Rc::clone(&self.class)
+    }
+
+    /// Borrows the host data, if it is a `T`.
+    pub fn borrow<T: Any>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.data.borrow(), |data| data.downcast_ref::<T>()).ok()
+    }
+
+    /// Mutably borrows the host data, if it is a `T`.
+    pub fn borrow_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.data.borrow_mut(), |data| data.downcast_mut::<T>()).ok()
+    }
+
+    /// Returns `name` bound to `receiver` as a callable native, or `None`
+    /// if the class has no such method.
+    pub(crate) fn bind(receiver: Gc<Foreign>, name: &str) -> Option<Native> {
+        let method = receiver.get().class.methods.borrow().get(name).cloned()?;
+        let function = Rc::new(BoundForeignMethod {
+            name: format!("{}.{name}", receiver.get().class.name),
+            receiver,
+            method,
+        });
+        Some(Native::bound(function, Value::Foreign(receiver)))
+    }
+}
+
+impl Trace for Foreign {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl HeapObject for Foreign {
+    const KIND: ObjectKind = ObjectKind::Foreign;
+}
+
+impl Debug for Foreign {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<foreign {}>", self.class.name)
+    }
+}
+
+impl Display for Foreign {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+struct BoundForeignMethod {
+    name: String,
+    receiver: Gc<Foreign>,
+    method: Rc<ForeignMethod>,
+}
+
+impl NativeFunc for BoundForeignMethod {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> Arity {
+        self.method.arity
+    }
+
+    fn call(&self, vm: &mut VM, args: Args) -> Result<Value, RuntimeError> {
+        (self.method.body)(vm, self.receiver, args)
+    }
+}
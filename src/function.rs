@@ -2,6 +2,7 @@ use std::fmt::Display;
 use std::rc::Rc;
 
 use crate::chunks::*;
+use crate::gc::*;
 
 #[derive(Debug, Default)]
 pub struct Function {
@@ -44,6 +45,18 @@ impl Display for Function {
     }
 }
 
+impl Trace for Function {
+    fn trace(&self, tracer: &mut Tracer) {
+        for constant in self.chunk.constants() {
+            tracer.mark_value(&constant);
+        }
+    }
+}
+
+impl HeapObject for Function {
+    const KIND: ObjectKind = ObjectKind::Function;
+}
+
 impl Function {
     pub fn new<T: Into<String>>(
         name: T,
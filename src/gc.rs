@@ -0,0 +1,602 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::instance::*;
+use crate::string::*;
+use crate::value::*;
+use crate::weak::*;
+
+/// Tuning knobs for the garbage collector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcConfig {
+    /// Bytes the heap may grow to before the first collection.
+    pub initial_threshold: usize,
+    /// After a collection, the next one runs once the heap has grown to
+    /// this multiple of the bytes that survived.
+    pub growth_factor: f64,
+    /// Collects before every instruction. Very slow; meant for flushing out
+    /// objects that are reachable but not traced.
+    pub stress: bool,
+}
+
+impl GcConfig {
+    pub const DEFAULT_INITIAL_THRESHOLD: usize = 1024 * 1024;
+    pub const DEFAULT_GROWTH_FACTOR: f64 = 2.0;
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_threshold: Self::DEFAULT_INITIAL_THRESHOLD,
+            growth_factor: Self::DEFAULT_GROWTH_FACTOR,
+            stress: cfg!(feature = "debug_stress_gc"),
+        }
+    }
+}
+
+/// An object that can hold references to other heap objects.
+pub trait Trace {
+    /// Marks every object and value directly reachable from `self`.
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+/// The kinds of object the heap keeps counts of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    String,
+    Function,
+    Native,
+    Closure,
+    Upvalue,
+    Class,
+    Instance,
+    BoundMethod,
+    Foreign,
+    WeakRef,
+    WeakMap,
+    List,
+    Map,
+}
+
+impl ObjectKind {
+    const COUNT: usize = 13;
+}
+
+/// A type the heap can allocate.
+///
+/// Not nameable outside the crate, so hosts cannot add heap types.
+pub trait HeapObject: Trace {
+    const KIND: ObjectKind;
+
+    /// Bytes the new object owns outside the heap's own allocation for it.
+    fn payload_size(&self) -> usize {
+        0
+    }
+}
+
+/// A snapshot of the heap, from [`VM::heap_stats`](crate::VM::heap_stats).
+///
+/// Object counts include everything not yet freed, so objects that became
+/// unreachable since the last collection are still counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub strings: usize,
+    pub functions: usize,
+    pub natives: usize,
+    pub closures: usize,
+    pub upvalues: usize,
+    pub classes: usize,
+    pub instances: usize,
+    pub bound_methods: usize,
+    pub foreign: usize,
+    pub weak_refs: usize,
+    pub weak_maps: usize,
+    pub lists: usize,
+    pub maps: usize,
+    /// Bytes currently held by heap objects.
+    pub bytes_allocated: usize,
+    /// The most `bytes_allocated` has been over the VM's lifetime.
+    pub peak_bytes: usize,
+    /// Collections run so far.
+    pub collections: usize,
+}
+
+impl HeapStats {
+    /// Total number of live objects.
+    pub fn objects(&self) -> usize {
+        self.strings
+            + self.functions
+            + self.natives
+            + self.closures
+            + self.upvalues
+            + self.classes
+            + self.instances
+            + self.bound_methods
+            + self.foreign
+            + self.weak_refs
+            + self.weak_maps
+            + self.lists
+            + self.maps
+    }
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "objects        {}", self.objects())?;
+        writeln!(f, "  strings      {}", self.strings)?;
+        writeln!(f, "  functions    {}", self.functions)?;
+        writeln!(f, "  natives      {}", self.natives)?;
+        writeln!(f, "  closures     {}", self.closures)?;
+        writeln!(f, "  upvalues     {}", self.upvalues)?;
+        writeln!(f, "  classes      {}", self.classes)?;
+        writeln!(f, "  instances    {}", self.instances)?;
+        writeln!(f, "  bound        {}", self.bound_methods)?;
+        writeln!(f, "  foreign      {}", self.foreign)?;
+        writeln!(f, "  weak refs    {}", self.weak_refs)?;
+        writeln!(f, "  weak maps    {}", self.weak_maps)?;
+        writeln!(f, "  lists        {}", self.lists)?;
+        writeln!(f, "  maps         {}", self.maps)?;
+        writeln!(f, "bytes          {}", self.bytes_allocated)?;
+        writeln!(f, "peak bytes     {}", self.peak_bytes)?;
+        writeln!(f, "collections    {}", self.collections)
+    }
+}
+
+struct GcBox<T: ?Sized> {
+    marked: Cell<bool>,
+    kind: ObjectKind,
+    // Never reused, so a handle to a freed object cannot be mistaken for a
+    // new one allocated at the same address.
+    id: u64,
+    // Bytes charged to this object, including what it owns.
+    size: Cell<usize>,
+    value: T,
+}
+
+type ObjectPtr = NonNull<GcBox<dyn Trace>>;
+
+// Shared by every heap, so that ids are unique across VMs too.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A handle to an object owned by a [`VM`](crate::VM)'s garbage-collected
+/// heap.
+///
+/// Handles are plain pointers: copying one does not keep the object alive.
+/// An object lives as long as it is reachable from the VM's globals, its
+/// stack, or a value pinned with [`VM::pin`](crate::VM::pin). Hosts reach
+/// the object through [`VM::object`](crate::VM::object), which checks that
+/// it is still alive, so a handle kept past a collection or past its VM is
+/// harmless.
+pub struct Gc<T: Trace + 'static> {
+    ptr: NonNull<GcBox<T>>,
+    id: u64,
+}
+
+impl<T: Trace + 'static> Gc<T> {
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    #[cfg(feature = "nan_boxing")]
+    pub(crate) fn as_ptr(this: Self) -> *const () {
+        this.ptr.as_ptr() as *const ()
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have come from [`Gc::as_ptr`] on a live `Gc<T>`.
+    #[cfg(feature = "nan_boxing")]
+    pub(crate) unsafe fn from_ptr(ptr: *const ()) -> Self {
+        // SAFETY: the caller guarantees `ptr` is a live `GcBox<T>`.
+        let ptr = unsafe { NonNull::new_unchecked(ptr as *mut GcBox<T>) };
+        Self { ptr, id: unsafe { ptr.as_ref() }.id }
+    }
+
+    /// An address identifying the object for as long as it lives.
+    pub(crate) fn addr(this: Self) -> usize {
+        this.ptr.as_ptr() as *const () as usize
+    }
+
+    fn is_marked(&self) -> bool {
+        self.header().marked.get()
+    }
+
+    /// Borrows the object. Inside the crate, handles come from the VM's own
+    /// roots and are alive for as long as they are used.
+    pub(crate) fn get(&self) -> &T {
+        &self.header().value
+    }
+
+    fn header(&self) -> &GcBox<T> {
+        // SAFETY: `self` is a live handle.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: Trace + 'static> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Gc::ptr_eq(self, other)
+    }
+}
+
+impl<T: Trace + 'static> Eq for Gc<T> {}
+
+impl<T: Trace + 'static> Hash for Gc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ptr.hash(state)
+    }
+}
+
+impl<T: Trace + 'static> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Trace + 'static> Copy for Gc<T> {}
+
+impl<T: Trace + 'static> Debug for Gc<T> {
+    // Objects may refer to themselves, so don't descend into them.
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Gc({:p})", self.ptr)
+    }
+}
+
+/// Marks reachable objects, keeping a worklist of those whose children
+/// have not been traced yet.
+pub struct Tracer {
+    gray: Vec<ObjectPtr>,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Self { gray: Vec::new() }
+    }
+
+    pub(crate) fn mark<T: Trace + 'static>(&mut self, object: Gc<T>) {
+        let ptr: ObjectPtr = object.ptr;
+        // SAFETY: `object` is a live handle.
+        let header = unsafe { ptr.as_ref() };
+        if !header.marked.replace(true) {
+            self.gray.push(ptr);
+        }
+    }
+
+    fn trace_gray(&mut self) {
+        while let Some(ptr) = self.gray.pop() {
+            // SAFETY: only live objects are ever marked.
+            unsafe { ptr.as_ref() }.value.trace(self);
+        }
+    }
+
+    pub(crate) fn mark_value(&mut self, value: &Value) {
+        match value {
+            Value::Closure(closure) => self.mark(*closure),
+            Value::Class(class) => self.mark(*class),
+            Value::Instance(instance) => self.mark(*instance),
+            Value::Bound(bound) => self.mark(*bound),
+            Value::Str(string) => self.mark(*string),
+            Value::Func(function) => self.mark(*function),
+            Value::Native(native) => self.mark(*native),
+            Value::Foreign(foreign) => self.mark(*foreign),
+            Value::Weak(weak) => self.mark(*weak),
+            Value::WeakMap(map) => self.mark(*map),
+            Value::List(list) => self.mark(*list),
+            Value::Map(map) => self.mark(*map),
+            Value::Boolean(_) | Value::Number(_) | Value::Nil => {}
+        }
+    }
+}
+
+/// Whether the collection in progress has found `value` reachable.
+/// Values that are not objects always are.
+pub(crate) fn is_marked(value: &Value) -> bool {
+    match value {
+        Value::Closure(closure) => closure.is_marked(),
+        Value::Class(class) => class.is_marked(),
+        Value::Instance(instance) => instance.is_marked(),
+        Value::Bound(bound) => bound.is_marked(),
+        Value::Str(string) => string.is_marked(),
+        Value::Func(function) => function.is_marked(),
+        Value::Native(native) => native.is_marked(),
+        Value::Foreign(foreign) => foreign.is_marked(),
+        Value::Weak(weak) => weak.is_marked(),
+        Value::WeakMap(map) => map.is_marked(),
+        Value::List(list) => list.is_marked(),
+        Value::Map(map) => map.is_marked(),
+        Value::Boolean(_) | Value::Number(_) | Value::Nil => true,
+    }
+}
+
+/// Owns every garbage-collected object created by a VM.
+pub(crate) struct Heap {
+    // Keyed by address, so that handles from the host can be checked.
+    objects: HashMap<usize, ObjectPtr>,
+    strings: Interner,
+    // Weak objects, which the collector must visit after marking.
+    weak_refs: Vec<Gc<WeakRef>>,
+    weak_maps: Vec<Gc<WeakMap>>,
+    // Instances whose class has a `deinit` method that has not run yet.
+    finalizable: Vec<Gc<Instance>>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+    memory_limit: Option<usize>,
+    live: [usize; ObjectKind::COUNT],
+    peak_bytes: usize,
+    collections: usize,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Self {
+        let config = GcConfig::default();
+        Self {
+            objects: HashMap::new(),
+            strings: Interner::default(),
+            weak_refs: Vec::new(),
+            weak_maps: Vec::new(),
+            finalizable: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: config.initial_threshold,
+            config,
+            memory_limit: None,
+            live: [0; ObjectKind::COUNT],
+            peak_bytes: 0,
+            collections: 0,
+        }
+    }
+
+    pub(crate) fn alloc<T: HeapObject + 'static>(&mut self, value: T) -> Gc<T> {
+        let object = Box::new(GcBox {
+            marked: Cell::new(false),
+            kind: T::KIND,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            size: Cell::new(0),
+            value,
+        });
+        let size = mem::size_of_val(&*object) + object.value.payload_size();
+        object.size.set(size);
+        self.bytes_allocated += size;
+        self.peak_bytes = self.peak_bytes.max(self.bytes_allocated);
+        self.live[T::KIND as usize] += 1;
+        let object_id = object.id;
+        let ptr = NonNull::from(Box::leak(object));
+        self.objects.insert(ptr.as_ptr() as *const () as usize, ptr);
+        Gc { ptr, id: object_id }
+    }
+
+    /// Bytes an object of type `T` with `payload` bytes of contents is
+    /// charged for, as [`Heap::alloc`] counts them.
+    pub(crate) fn object_size<T: HeapObject>(payload: usize) -> usize {
+        mem::size_of::<GcBox<T>>() + payload
+    }
+
+    /// Borrows the object behind `handle` if it is alive in this heap.
+    pub(crate) fn get<T: HeapObject + 'static>(&self, handle: Gc<T>) -> Option<&T> {
+        let ptr = self.objects.get(&Gc::addr(handle))?;
+        // SAFETY: every pointer in `objects` is live, and stays so while
+        // `self` is borrowed. Checking the id guards against a new object
+        // at the same address, whatever its type.
+        unsafe {
+            if ptr.as_ref().id != handle.id {
+                return None;
+            }
+            Some(&handle.ptr.as_ref().value)
+        }
+    }
+
+    /// Whether `value` is a primitive or an object alive in this heap.
+    pub(crate) fn contains(&self, value: &Value) -> bool {
+        match value {
+            Value::Closure(closure) => self.get(*closure).is_some(),
+            Value::Class(class) => self.get(*class).is_some(),
+            Value::Instance(instance) => self.get(*instance).is_some(),
+            Value::Bound(bound) => self.get(*bound).is_some(),
+            Value::Str(string) => self.get(*string).is_some(),
+            Value::Func(function) => self.get(*function).is_some(),
+            Value::Native(native) => self.get(*native).is_some(),
+            Value::Foreign(foreign) => self.get(*foreign).is_some(),
+            Value::Weak(weak) => self.get(*weak).is_some(),
+            Value::WeakMap(map) => self.get(*map).is_some(),
+            Value::List(list) => self.get(*list).is_some(),
+            Value::Map(map) => self.get(*map).is_some(),
+            Value::Boolean(_) | Value::Number(_) | Value::Nil => true,
+        }
+    }
+
+    pub(crate) fn alloc_weak_ref(&mut self, target: Value) -> Gc<WeakRef> {
+        let weak = self.alloc(WeakRef::new(target));
+        self.weak_refs.push(weak);
+        weak
+    }
+
+    pub(crate) fn alloc_weak_map(&mut self) -> Gc<WeakMap> {
+        let map = self.alloc(WeakMap::default());
+        self.weak_maps.push(map);
+        map
+    }
+
+    /// Charges `object` for `bytes` more memory it has taken on since it
+    /// was allocated.
+    pub(crate) fn grow<T: Trace + 'static>(&mut self, object: Gc<T>, bytes: usize) {
+        let size = &object.header().size;
+        size.set(size.get() + bytes);
+        self.bytes_allocated += bytes;
+        self.peak_bytes = self.peak_bytes.max(self.bytes_allocated);
+    }
+
+    /// Undoes [`Heap::grow`] for memory `object` has given back.
+    pub(crate) fn shrink<T: Trace + 'static>(&mut self, object: Gc<T>, bytes: usize) {
+        let size = &object.header().size;
+        size.set(size.get() - bytes);
+        self.bytes_allocated -= bytes;
+    }
+
+    /// Has the next collection that finds `instance` unreachable hand it
+    /// back for finalizing instead of freeing it.
+    pub(crate) fn register_finalizer(&mut self, instance: Gc<Instance>) {
+        self.finalizable.push(instance);
+    }
+
+    /// Unregisters every instance still waiting to be found unreachable,
+    /// oldest first.
+    pub(crate) fn take_finalizable(&mut self) -> Vec<Gc<Instance>> {
+        mem::take(&mut self.finalizable)
+    }
+
+    /// Returns the string with contents `text`, creating it if no such
+    /// string exists yet.
+    pub(crate) fn intern(&mut self, text: &str) -> Gc<Str> {
+        if let Some(string) = self.strings.get(text) {
+            return string;
+        }
+        let string = self.alloc(Str::new(text));
+        self.strings.insert(string);
+        string
+    }
+
+    /// Returns the string with contents `text` only if it already exists.
+    pub(crate) fn lookup(&self, text: &str) -> Option<Gc<Str>> {
+        self.strings.get(text)
+    }
+
+    pub(crate) fn config(&self) -> &GcConfig {
+        &self.config
+    }
+
+    pub(crate) fn set_config(&mut self, config: GcConfig) {
+        self.config = config;
+        self.next_gc = self.threshold(self.config.initial_threshold.max(self.bytes_allocated));
+    }
+
+    pub(crate) fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+        self.next_gc = self.threshold(self.next_gc);
+    }
+
+    /// Whether the heap, plus `extra` bytes held elsewhere, is over the
+    /// memory limit.
+    pub(crate) fn over_limit(&self, extra: usize) -> bool {
+        self.memory_limit
+            .is_some_and(|limit| self.bytes_allocated + extra > limit)
+    }
+
+    // Collections must start by the time the heap reaches the memory limit,
+    // so that going over it is noticed without a separate check.
+    fn threshold(&self, next_gc: usize) -> usize {
+        self.memory_limit.map_or(next_gc, |limit| next_gc.min(limit))
+    }
+
+    pub(crate) fn stats(&self) -> HeapStats {
+        let live = |kind: ObjectKind| self.live[kind as usize];
+        HeapStats {
+            strings: live(ObjectKind::String),
+            functions: live(ObjectKind::Function),
+            natives: live(ObjectKind::Native),
+            closures: live(ObjectKind::Closure),
+            upvalues: live(ObjectKind::Upvalue),
+            classes: live(ObjectKind::Class),
+            instances: live(ObjectKind::Instance),
+            bound_methods: live(ObjectKind::BoundMethod),
+            foreign: live(ObjectKind::Foreign),
+            weak_refs: live(ObjectKind::WeakRef),
+            weak_maps: live(ObjectKind::WeakMap),
+            lists: live(ObjectKind::List),
+            maps: live(ObjectKind::Map),
+            bytes_allocated: self.bytes_allocated,
+            peak_bytes: self.peak_bytes,
+            collections: self.collections,
+        }
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Traces everything reachable from the roots marked in `tracer` and
+    /// frees the rest.
+    ///
+    /// Unreachable instances registered with [`Heap::register_finalizer`]
+    /// are not freed but returned, along with everything they reach, so
+    /// that their `deinit` can run. Weak references to them are cleared
+    /// first, and they are not registered any more, so a finalizer runs
+    /// at most once.
+    pub(crate) fn collect(&mut self, mut tracer: Tracer) -> Vec<Gc<Instance>> {
+        self.trace(&mut tracer);
+
+        let (doomed, finalizable) = self
+            .finalizable
+            .drain(..)
+            .partition(|instance| !instance.is_marked());
+        self.finalizable = finalizable;
+        for map in self.weak_maps.clone() {
+            let freed = map.get().sweep();
+            self.shrink(map, freed);
+        }
+        for weak in &self.weak_refs {
+            weak.get().sweep();
+        }
+
+        for instance in &doomed {
+            tracer.mark(*instance);
+        }
+        self.trace(&mut tracer);
+        self.weak_maps.retain(|map| map.is_marked());
+        self.weak_refs.retain(|weak| weak.is_marked());
+
+        self.strings.retain(|string| string.is_marked());
+        let mut freed = 0;
+        let live = &mut self.live;
+        self.objects.retain(|_, &mut ptr| {
+            // SAFETY: every pointer in `objects` is live until freed here.
+            let header = unsafe { ptr.as_ref() };
+            if header.marked.replace(false) {
+                true
+            } else {
+                freed += header.size.get();
+                live[header.kind as usize] -= 1;
+                // SAFETY: the object is unreachable, so nothing uses it.
+                drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+                false
+            }
+        });
+        self.bytes_allocated -= freed;
+        self.collections += 1;
+        self.next_gc = self.threshold(
+            ((self.bytes_allocated as f64 * self.config.growth_factor) as usize)
+                .max(self.config.initial_threshold),
+        );
+        doomed
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_gray();
+        // A weak map's values are reachable only through live keys, and
+        // marking them may bring more keys to life.
+        loop {
+            for map in &self.weak_maps {
+                if map.is_marked() {
+                    map.get().trace_entries(tracer);
+                }
+            }
+            if tracer.gray.is_empty() {
+                break;
+            }
+            tracer.trace_gray();
+        }
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for (_, ptr) in self.objects.drain() {
+            // SAFETY: the heap owns all of its objects.
+            drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+        }
+    }
+}
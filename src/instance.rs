@@ -1,42 +1,60 @@
-use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use::std::fmt::{Display, Result, Formatter};
 
 use crate::value::*;
 use crate::class::*;
+use crate::gc::*;
+use crate::string::*;
 
 
 #[derive(Debug)]
 pub struct Instance {
-    klass: Rc<Class>,
-    fields: RefCell<HashMap<String, Value>>,
+    klass: Gc<Class>,
+    fields: RefCell<HashMap<Gc<Str>, Value>>,
 }
 
 
 impl Instance {
-    pub fn new(klass: Rc<Class>) -> Self {
+    /// Memory charged for each field.
+    pub(crate) const FIELD_SIZE: usize = std::mem::size_of::<(Gc<Str>, Value)>();
+
+    pub fn new(klass: Gc<Class>) -> Self {
         Self { 
-            klass: Rc::clone(&klass), 
+            klass,
             fields: RefCell::new(HashMap::new())
         }
     }
 
-    pub fn get_field(&self, field_name:&String) -> Option<Value> {
-        self.fields.borrow().get(field_name).cloned()
+    pub fn get_field(&self, field_name: Gc<Str>) -> Option<Value> {
+        self.fields.borrow().get(&field_name).cloned()
     }
 
-    pub fn set_field<T:Into<String>>(&self, field_name:T, value:&Value) {
-         self.fields.borrow_mut().insert(field_name.into(), value.clone());
+    pub(crate) fn set_field(&self, field_name: Gc<Str>, value:&Value) {
+         self.fields.borrow_mut().insert(field_name, *value);
     }
 
-    pub fn get_class(&self) -> Rc<Class> {
-        Rc::clone(&self.klass)
+    pub fn get_class(&self) -> Gc<Class> {
+        self.klass
     }
 }
 
+impl Trace for Instance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.klass);
+        for (name, value) in self.fields.borrow().iter() {
+            tracer.mark(*name);
+            tracer.mark_value(value);
+        }
+    }
+}
+
+impl HeapObject for Instance {
+    const KIND: ObjectKind = ObjectKind::Instance;
+}
+
 impl Display for Instance {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "{} instacne", self.klass)
+        write!(f, "{} instacne", self.klass.get())
     }
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+//! A bytecode virtual machine for the Lox language.
+//!
+//! The crate can be embedded in other Rust programs: create a [`VM`],
+//! register any native functions the host wants to expose, and hand it
+//! source code with [`VM::interpret`].
+//!
+//! ```no_run
+//! use lox_bytecode::{InterpretResult, VM};
+//!
+//! let mut vm = VM::new();
+//! match vm.interpret("var answer = 6 * 7;") {
+//!     Ok(()) => println!("answer = {}", vm.get_global("answer").unwrap().display(&vm)),
+//!     Err(InterpretResult::CompileError(diagnostics)) => {
+//!         for diagnostic in diagnostics {
+//!             eprintln!("{diagnostic}");
+//!         }
+//!     }
+//!     Err(InterpretResult::RuntimeError(error)) => eprintln!("{error}"),
+//! }
+//! ```
+
+mod bound_method;
+mod chunks;
+mod class;
+mod closure;
+mod compliler;
+mod diagnostic;
+mod error;
+mod foreign;
+mod function;
+mod gc;
+mod instance;
+mod limits;
+mod list;
+mod map;
+mod native;
+mod output;
+mod packed;
+mod report;
+mod scanner;
+mod span;
+mod string;
+mod token;
+mod token_type;
+mod upvalue;
+mod value;
+mod vm;
+mod weak;
+
+pub use bound_method::BoundMethod;
+pub use class::Class;
+pub use closure::Closure;
+pub use compliler::Compiler;
+pub use diagnostic::{Diagnostic, ErrorCode, ErrorSite, Severity};
+pub use error::{InterpretResult, RuntimeError, RuntimeErrorKind, StackFrame};
+pub use foreign::{Foreign, ForeignClass};
+pub use function::Function;
+pub use gc::{Gc, GcConfig, HeapStats};
+pub use instance::Instance;
+pub use limits::{InterruptHandle, Limits};
+pub use list::List;
+pub use map::Map;
+pub use report::{Label, Renderer, Report};
+pub use span::Span;
+pub use string::Str;
+pub use native::{Args, Arity, NativeFn, NativeFunc};
+pub use value::Value;
+pub use vm::VM;
+pub use weak::{WeakMap, WeakRef};
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Bounds on how much work a script may do, for running untrusted code.
+///
+/// The instruction budget and the timeout apply to each call from the host
+/// ([`VM::interpret`](crate::VM::interpret) or one of the `call_*` methods)
+/// and restart with the next one. Calls that natives make back into Lox
+/// count against the call that is already running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of bytecode instructions to execute.
+    pub max_instructions: Option<u64>,
+    /// Maximum depth of nested Lox calls.
+    pub max_frames: usize,
+    /// Maximum number of slots on the value stack.
+    pub max_stack: Option<usize>,
+    /// Wall-clock time allowed before the script is stopped.
+    pub timeout: Option<Duration>,
+    /// Bytes the VM's objects and value stack may take up. Unlike the
+    /// other limits this covers everything the VM holds, not one call.
+    pub max_memory: Option<usize>,
+}
+
+impl Limits {
+    pub const DEFAULT_MAX_FRAMES: usize = 256;
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_instructions: None,
+            max_frames: Self::DEFAULT_MAX_FRAMES,
+            max_stack: None,
+            timeout: None,
+            max_memory: None,
+        }
+    }
+}
+
+/// Stops a running script from another thread or a signal handler.
+///
+/// Get one from [`VM::interrupt_handle`](crate::VM::interrupt_handle). The
+/// VM polls the flag between instructions; once it is set the script stops
+/// with a [`RuntimeErrorKind::Interrupted`](crate::RuntimeErrorKind::Interrupted)
+/// error, and the VM clears the flag when it returns to the host. Setting
+/// the flag only stores to an atomic, so it is safe to do from a signal
+/// handler.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.flag.store(false, Ordering::Relaxed);
+    }
+}
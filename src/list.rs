@@ -0,0 +1,199 @@
+use std::cell::{Ref, RefCell};
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::mem;
+
+use crate::error::*;
+use crate::gc::*;
+use crate::native::*;
+use crate::value::*;
+
+/// A growable Lox list, written `[a, b, c]` in scripts.
+pub struct List {
+    items: RefCell<Vec<Value>>,
+}
+
+impl List {
+    // Memory charged for each element.
+    pub(crate) const ITEM_SIZE: usize = mem::size_of::<Value>();
+
+    pub(crate) fn new(items: Vec<Value>) -> Self {
+        Self {
+            items: RefCell::new(items),
+        }
+    }
+
+    pub fn items(&self) -> Ref<'_, Vec<Value>> {
+        self.items.borrow()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.borrow().is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.items.borrow().get(index).copied()
+    }
+
+    /// Replaces element `index`. Returns `false` if it is out of bounds.
+    pub(crate) fn set(&self, index: usize, value: Value) -> bool {
+        match self.items.borrow_mut().get_mut(index) {
+            Some(item) => {
+                *item = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Resolves a script index, which counts from the end when negative,
+    /// to a position in `0..len`.
+    pub(crate) fn index(&self, index: Value) -> Result<usize, RuntimeError> {
+        let len = self.len();
+        let position = integer(index, "List index")?;
+        let position = if position < 0.0 {
+            position + len as f64
+        } else {
+            position
+        };
+        if position < 0.0 || position >= len as f64 {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::Index,
+                format!("List index {} out of bounds for length {len}.", index.show()),
+            ));
+        }
+        Ok(position as usize)
+    }
+
+    /// The elements between `start` and `end`, either of which may be
+    /// `nil` for the start or end of the list. As in Python, negative
+    /// bounds count from the end and bounds past either end are clamped.
+    pub(crate) fn slice(&self, start: Value, end: Value) -> Result<Vec<Value>, RuntimeError> {
+        let len = self.len() as f64;
+        let bound = |value: Value, default: f64| -> Result<usize, RuntimeError> {
+            if let Value::Nil = value {
+                return Ok(default as usize);
+            }
+            let bound = integer(value, "Slice bound")?;
+            let bound = if bound < 0.0 { bound + len } else { bound };
+            Ok(bound.clamp(0.0, len) as usize)
+        };
+        let start = bound(start, 0.0)?;
+        let end = bound(end, len)?;
+        let items = self.items.borrow();
+        Ok(items[start..end.max(start)].to_vec())
+    }
+
+    pub(crate) fn method(name: &str) -> Option<(Arity, BuiltinMethodBody)> {
+        let method: (Arity, BuiltinMethodBody) = match name {
+            "push" => (Arity::Fixed(1), |vm, list, args| {
+                let list = as_list(list);
+                vm.reserve(Self::ITEM_SIZE)?;
+                list.get().items.borrow_mut().push(args.get(0));
+                vm.heap_mut().grow(list, Self::ITEM_SIZE);
+                Ok(Value::Nil)
+            }),
+            "pop" => (Arity::Fixed(0), |vm, list, _| {
+                let list = as_list(list);
+                let item = list.get().items.borrow_mut().pop();
+                match item {
+                    Some(item) => {
+                        vm.heap_mut().shrink(list, Self::ITEM_SIZE);
+                        Ok(item)
+                    }
+                    None => Err(RuntimeError::new(
+                        RuntimeErrorKind::Index,
+                        "Can't pop from an empty list.",
+                    )),
+                }
+            }),
+            "insert" => (Arity::Fixed(2), |vm, list, args| {
+                let list = as_list(list);
+                // Inserting at the length appends.
+                let position = if integer(args.get(0), "List index")? == list.get().len() as f64 {
+                    list.get().len()
+                } else {
+                    list.get().index(args.get(0))?
+                };
+                vm.reserve(Self::ITEM_SIZE)?;
+                list.get().items.borrow_mut().insert(position, args.get(1));
+                vm.heap_mut().grow(list, Self::ITEM_SIZE);
+                Ok(Value::Nil)
+            }),
+            "remove" => (Arity::Fixed(1), |vm, list, args| {
+                let list = as_list(list);
+                let position = list.get().index(args.get(0))?;
+                let item = list.get().items.borrow_mut().remove(position);
+                vm.heap_mut().shrink(list, Self::ITEM_SIZE);
+                Ok(item)
+            }),
+            "len" => (Arity::Fixed(0), |_, list, _| {
+                Ok(Value::Number(as_list(list).get().len() as f64))
+            }),
+            "contains" => (Arity::Fixed(1), |_, list, args| {
+                let item = args.get(0);
+                Ok(Value::Boolean(as_list(list).get().items.borrow().contains(&item)))
+            }),
+            _ => return None,
+        };
+        Some(method)
+    }
+}
+
+fn as_list(receiver: Value) -> Gc<List> {
+    match receiver {
+        Value::List(list) => list,
+        _ => unreachable!("List method bound to {receiver:?}"),
+    }
+}
+
+/// Checks that `value` is a whole number, for use as an index.
+fn integer(value: Value, what: &str) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 => Ok(n),
+        _ => Err(RuntimeError::new(
+            RuntimeErrorKind::Type,
+            format!("{what} must be an integer."),
+        )),
+    }
+}
+
+impl Trace for List {
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in self.items.borrow().iter() {
+            tracer.mark_value(item);
+        }
+    }
+}
+
+impl HeapObject for List {
+    const KIND: ObjectKind = ObjectKind::List;
+
+    fn payload_size(&self) -> usize {
+        self.len() * Self::ITEM_SIZE
+    }
+}
+
+impl Debug for List {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<list of {}>", self.len())
+    }
+}
+
+impl Display for List {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        format_container(self, "[...]", f, |f| {
+            f.write_str("[")?;
+            for (i, item) in self.items.borrow().iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", item.show())?;
+            }
+            f.write_str("]")
+        })
+    }
+}
@@ -1,36 +1,37 @@
 use std::env::args;
-use std::io::{BufRead, Result, Write, stdin, stdout};
+use std::io::{BufRead, IsTerminal, Result, Write, stderr, stdin, stdout};
 
-mod vm;
-use vm::*;
-mod chunks;
-mod compliler;
-mod error;
-mod function;
-mod scanner;
-mod token;
-mod token_type;
-mod value;
-use error::*;
-mod closure;
-mod native;
-mod upvalue;
-mod class;
-mod instance;
-mod bound_method;
+use lox_bytecode::{InterpretResult, Renderer, Report, VM};
 
 fn main() {
-    let args: Vec<String> = args().collect();
+    let mut args: Vec<String> = args().skip(1).collect();
+    let stats = if let Some(index) = args.iter().position(|arg| arg == "--stats") {
+        args.remove(index);
+        true
+    } else {
+        false
+    };
     let mut vm = VM::new();
 
-    match args.len() {
-        1 => repl(&mut vm),
-        2 => run_file(&mut vm, &args[1]).expect("Could not run the file"),
+    let code = match args.as_slice() {
+        [] => {
+            repl(&mut vm);
+            0
+        }
+        [path] => run_file(&mut vm, path).expect("Could not run the file"),
         _ => {
-            println!("Usage: lox-bytecode [path]");
+            println!("Usage: lox-bytecode [--stats] [path]");
             std::process::exit(64);
         }
+    };
+    if stats {
+        let _ = vm.flush_output();
+        eprint!("{}", vm.heap_stats());
     }
+    // `exit` skips destructors, and dropping the VM runs the finalizers
+    // that are still pending.
+    drop(vm);
+    std::process::exit(code);
 }
 
 fn repl(vm: &mut VM) {
@@ -42,7 +43,9 @@ fn repl(vm: &mut VM) {
             if line.is_empty() {
                 break;
             }
-            let _ = vm.interpret(&line);
+            if let Err(error) = vm.interpret(&line) {
+                report(Renderer::new(&line).with_name("<repl>"), &error);
+            }
         } else {
             break;
         }
@@ -51,11 +54,31 @@ fn repl(vm: &mut VM) {
     }
 }
 
-fn run_file(vm: &mut VM, path: &str) -> Result<()> {
+fn run_file(vm: &mut VM, path: &str) -> Result<i32> {
     let buf = std::fs::read_to_string(path)?;
-    match vm.interpret(&buf) {
-        Err(InterpretResult::CompileError) => std::process::exit(65),
-        Err(InterpretResult::RuntimeError) => std::process::exit(70),
-        _ => std::process::exit(0),
+    let (source, name) = (buf.clone(), path.to_string());
+    vm.set_finalizer_error_handler(move |error| {
+        let renderer = Renderer::new(&source).with_name(name.as_str());
+        eprint!("{}", renderer.with_color(stderr().is_terminal()).render(&Report::from(error)));
+    });
+    let result = vm.interpret(&buf);
+    if let Err(error) = &result {
+        report(Renderer::new(&buf).with_name(path), error);
+    }
+    Ok(match result {
+        Err(InterpretResult::CompileError(_)) => 65,
+        Err(InterpretResult::RuntimeError(_)) => 70,
+        Ok(()) => 0,
+    })
+}
+
+fn report(renderer: Renderer, error: &InterpretResult) {
+    let renderer = renderer.with_color(stderr().is_terminal());
+    let reports: Vec<Report> = match error {
+        InterpretResult::CompileError(diagnostics) => diagnostics.iter().map(Report::from).collect(),
+        InterpretResult::RuntimeError(error) => vec![Report::from(error)],
+    };
+    for report in &reports {
+        eprint!("{}", renderer.render(report));
     }
 }
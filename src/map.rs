@@ -0,0 +1,229 @@
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::mem;
+
+use crate::error::*;
+use crate::gc::*;
+use crate::list::*;
+use crate::native::*;
+use crate::string::*;
+use crate::value::*;
+
+/// A Lox hash map, written `{"key": value}` in scripts.
+///
+/// Keys may be strings, numbers, booleans or `nil`. Entries are kept in
+/// the order they were first added.
+pub struct Map {
+    entries: RefCell<Vec<(Value, Value)>>,
+    // Position of each key's entry in `entries`.
+    index: RefCell<HashMap<Key, usize>>,
+}
+
+// A map key, hashed by value. Strings are interned, so hashing the handle
+// hashes the text.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Nil,
+    Boolean(bool),
+    Number(u64),
+    Str(Gc<Str>),
+}
+
+impl Key {
+    fn new(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Nil => Ok(Key::Nil),
+            Value::Boolean(b) => Ok(Key::Boolean(b)),
+            Value::Number(n) if n.is_nan() => Err(RuntimeError::new(
+                RuntimeErrorKind::Type,
+                "Map keys can't be NaN.",
+            )),
+            // `0 == -0` in Lox, so they must name the same entry.
+            Value::Number(0.0) => Ok(Key::Number(0.0f64.to_bits())),
+            Value::Number(n) => Ok(Key::Number(n.to_bits())),
+            Value::Str(s) => Ok(Key::Str(s)),
+            _ => Err(RuntimeError::new(
+                RuntimeErrorKind::Type,
+                "Map keys must be strings, numbers, booleans or nil.",
+            )),
+        }
+    }
+}
+
+impl Map {
+    // Memory charged for each entry.
+    pub(crate) const ENTRY_SIZE: usize =
+        mem::size_of::<(Value, Value)>() + mem::size_of::<(Key, usize)>();
+
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: RefCell::new(Vec::new()),
+            index: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The `(key, value)` pairs in insertion order.
+    pub fn entries(&self) -> Ref<'_, Vec<(Value, Value)>> {
+        self.entries.borrow()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<Value> {
+        let position = self.position(Key::new(*key).ok()?)?;
+        Some(self.entries.borrow()[position].1)
+    }
+
+    pub fn has(&self, key: &Value) -> bool {
+        Key::new(*key).is_ok_and(|key| self.position(key).is_some())
+    }
+
+    fn position(&self, key: Key) -> Option<usize> {
+        self.index.borrow().get(&key).copied()
+    }
+
+    /// The value for `key`, or a `Key` error naming it if there is none.
+    pub(crate) fn lookup(&self, key: Value) -> Result<Value, RuntimeError> {
+        match self.position(Key::new(key)?) {
+            Some(position) => Ok(self.entries.borrow()[position].1),
+            None => Err(RuntimeError::new(
+                RuntimeErrorKind::Key,
+                format!("Key {} not found in map.", key.show()),
+            )),
+        }
+    }
+
+    /// Sets the value for `key`. Returns whether this added an entry.
+    pub(crate) fn insert(&self, key: Value, value: Value) -> Result<bool, RuntimeError> {
+        let hashed = Key::new(key)?;
+        let mut entries = self.entries.borrow_mut();
+        match self.position(hashed) {
+            Some(position) => {
+                entries[position].1 = value;
+                Ok(false)
+            }
+            None => {
+                self.index.borrow_mut().insert(hashed, entries.len());
+                entries.push((key, value));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Removes the entry for `key`, returning its value.
+    pub(crate) fn remove(&self, key: Value) -> Result<Option<Value>, RuntimeError> {
+        let hashed = Key::new(key)?;
+        let Some(position) = self.index.borrow_mut().remove(&hashed) else {
+            return Ok(None);
+        };
+        let mut entries = self.entries.borrow_mut();
+        let (_, value) = entries.remove(position);
+        // Later entries each moved down one place.
+        for (key, _) in &entries[position..] {
+            if let Ok(key) = Key::new(*key)
+                && let Some(moved) = self.index.borrow_mut().get_mut(&key)
+            {
+                *moved -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub(crate) fn method(name: &str) -> Option<(Arity, BuiltinMethodBody)> {
+        let method: (Arity, BuiltinMethodBody) = match name {
+            "keys" => (Arity::Fixed(0), |vm, map, _| {
+                vm.reserve(Heap::object_size::<List>(as_map(map).get().len() * List::ITEM_SIZE))?;
+                let keys = as_map(map).get().entries().iter().map(|(key, _)| *key).collect();
+                Ok(Value::List(vm.heap_mut().alloc(List::new(keys))))
+            }),
+            "values" => (Arity::Fixed(0), |vm, map, _| {
+                vm.reserve(Heap::object_size::<List>(as_map(map).get().len() * List::ITEM_SIZE))?;
+                let values = as_map(map).get().entries().iter().map(|(_, value)| *value).collect();
+                Ok(Value::List(vm.heap_mut().alloc(List::new(values))))
+            }),
+            "entries" => (Arity::Fixed(0), |vm, map, _| {
+                let len = as_map(map).get().len();
+                let pair = Heap::object_size::<List>(2 * List::ITEM_SIZE);
+                vm.reserve(len * pair + Heap::object_size::<List>(len * List::ITEM_SIZE))?;
+                // Nothing collects once the room is reserved, so the pairs
+                // are safe until the outer list holds them.
+                let pairs = as_map(map)
+                    .get().entries()
+                    .iter()
+                    .map(|&(key, value)| Value::List(vm.heap_mut().alloc(List::new(vec![key, value]))))
+                    .collect();
+                Ok(Value::List(vm.heap_mut().alloc(List::new(pairs))))
+            }),
+            "has" => (Arity::Fixed(1), |_, map, args| {
+                let key = args.get(0);
+                Key::new(key)?;
+                Ok(Value::Boolean(as_map(map).get().has(&key)))
+            }),
+            "remove" => (Arity::Fixed(1), |vm, map, args| {
+                let map = as_map(map);
+                let removed = map.get().remove(args.get(0))?;
+                if removed.is_some() {
+                    vm.heap_mut().shrink(map, Self::ENTRY_SIZE);
+                }
+                Ok(removed.unwrap_or(Value::Nil))
+            }),
+            "len" => (Arity::Fixed(0), |_, map, _| {
+                Ok(Value::Number(as_map(map).get().len() as f64))
+            }),
+            _ => return None,
+        };
+        Some(method)
+    }
+}
+
+fn as_map(receiver: Value) -> Gc<Map> {
+    match receiver {
+        Value::Map(map) => map,
+        _ => unreachable!("Map method bound to {receiver:?}"),
+    }
+}
+
+impl Trace for Map {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (key, value) in self.entries.borrow().iter() {
+            tracer.mark_value(key);
+            tracer.mark_value(value);
+        }
+    }
+}
+
+impl HeapObject for Map {
+    const KIND: ObjectKind = ObjectKind::Map;
+
+    fn payload_size(&self) -> usize {
+        self.len() * Self::ENTRY_SIZE
+    }
+}
+
+impl Debug for Map {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<map of {}>", self.len())
+    }
+}
+
+impl Display for Map {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        format_container(self, "{...}", f, |f| {
+            f.write_str("{")?;
+            for (i, (key, value)) in self.entries.borrow().iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}: {}", key.show(), value.show())?;
+            }
+            f.write_str("}")
+        })
+    }
+}
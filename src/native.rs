@@ -1,16 +1,296 @@
-use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::rc::Rc;
 use std::time::SystemTime;
 
+use crate::error::*;
+use crate::foreign::*;
+use crate::gc::*;
 use crate::value::*;
+use crate::vm::*;
+
+/// How many arguments a native function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    /// Any number of arguments, but at least this many.
+    Variadic(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, arg_count: usize) -> bool {
+        match self {
+            Arity::Fixed(n) => arg_count == *n,
+            Arity::Variadic(min) => arg_count >= *min,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Arity::Fixed(n) => write!(f, "{n}"),
+            Arity::Variadic(min) => write!(f, "at least {min}"),
+        }
+    }
+}
+
+/// A function implemented in Rust and callable from Lox scripts.
+///
+/// The VM checks the argument count against [`NativeFunc::arity`] before
+/// calling, so `call` only sees argument lists of an accepted length. An
+/// `Err` is raised as a runtime error at the call site, with the native's
+/// name on the stack trace. `vm` is the calling VM, which the native may
+/// use to call back into Lox code.
+pub trait NativeFunc {
+    fn name(&self) -> &str;
+
+    fn arity(&self) -> Arity;
+
+    fn call(&self, vm: &mut VM, args: Args) -> Result<Value, RuntimeError>;
+}
+
+impl Debug for dyn NativeFunc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<native fn {}>", self.name())
+    }
+}
+
+/// A native function as a Lox value.
+pub struct Native {
+    function: Rc<dyn NativeFunc>,
+    // Set for methods of foreign and built-in objects, whose receiver must
+    // live as long as they do.
+    receiver: Option<Value>,
+}
+
+impl Native {
+    pub(crate) fn new(function: Rc<dyn NativeFunc>) -> Self {
+        Self {
+            function,
+            receiver: None,
+        }
+    }
+
+    pub(crate) fn bound(function: Rc<dyn NativeFunc>, receiver: Value) -> Self {
+        Self {
+            function,
+            receiver: Some(receiver),
+        }
+    }
+
+    pub fn function(&self) -> &Rc<dyn NativeFunc> {
+        &self.function
+    }
+
+    pub fn name(&self) -> &str {
+        self.function.name()
+    }
+}
+
+impl Trace for Native {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(receiver) = &self.receiver {
+            tracer.mark_value(receiver);
+        }
+    }
+}
+
+impl HeapObject for Native {
+    const KIND: ObjectKind = ObjectKind::Native;
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<native fn {}>", self.name())
+    }
+}
+
+impl Display for Native {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<native fn {}>", self.name())
+    }
+}
+
+/// The arguments passed to a native function.
+pub struct Args<'a> {
+    // The type a built-in method belongs to, which qualifies `name`.
+    type_name: Option<&'a str>,
+    name: &'a str,
+    values: &'a [Value],
+}
+
+impl<'a> Args<'a> {
+    pub(crate) fn new(name: &'a str, values: &'a [Value]) -> Self {
+        Self {
+            type_name: None,
+            name,
+            values,
+        }
+    }
+
+    /// Arguments to built-in method `name` of `type_name`, named as
+    /// `List.push` is without building the string up front.
+    pub(crate) fn method(type_name: &'a str, name: &'a str, values: &'a [Value]) -> Self {
+        Self {
+            type_name: Some(type_name),
+            name,
+            values,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns a copy of argument `index`, or `nil` past the end.
+    pub fn get(&self, index: usize) -> Value {
+        self.values.get(index).cloned().unwrap_or(Value::Nil)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter()
+    }
+
+    pub fn number(&self, index: usize) -> Result<f64, RuntimeError> {
+        match self.get(index) {
+            Value::Number(n) => Ok(n),
+            _ => Err(self.type_error(index, "a number")),
+        }
+    }
+
+    pub fn string(&self, index: usize) -> Result<String, RuntimeError> {
+        match self.get(index) {
+            Value::Str(s) => Ok(s.get().as_str().to_string()),
+            _ => Err(self.type_error(index, "a string")),
+        }
+    }
+
+    pub fn boolean(&self, index: usize) -> Result<bool, RuntimeError> {
+        match self.get(index) {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(self.type_error(index, "a boolean")),
+        }
+    }
+
+    pub fn foreign(&self, index: usize) -> Result<Gc<Foreign>, RuntimeError> {
+        match self.get(index) {
+            Value::Foreign(foreign) => Ok(foreign),
+            _ => Err(self.type_error(index, "a foreign object")),
+        }
+    }
+
+    fn type_error(&self, index: usize, expected: &str) -> RuntimeError {
+        let name = match self.type_name {
+            Some(type_name) => format!("{type_name}.{}", self.name),
+            None => self.name.to_string(),
+        };
+        RuntimeError::new(
+            RuntimeErrorKind::Type,
+            format!("Argument {} to '{name}' must be {expected}.", index + 1),
+        )
+    }
+}
+
+type NativeFnBody = dyn Fn(&mut VM, Args) -> Result<Value, RuntimeError>;
+
+/// A native function built from a Rust closure.
+pub struct NativeFn {
+    name: String,
+    arity: Arity,
+    body: Box<NativeFnBody>,
+}
+
+impl NativeFn {
+    pub fn new<T, F>(name: T, arity: Arity, body: F) -> Self
+    where
+        T: Into<String>,
+        F: Fn(&mut VM, Args) -> Result<Value, RuntimeError> + 'static,
+    {
+        Self {
+            name: name.into(),
+            arity,
+            body: Box::new(body),
+        }
+    }
+}
+
+impl NativeFunc for NativeFn {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, vm: &mut VM, args: Args) -> Result<Value, RuntimeError> {
+        (self.body)(vm, args)
+    }
+}
+
+pub(crate) type BuiltinMethodBody = fn(&mut VM, Value, Args) -> Result<Value, RuntimeError>;
+
+/// A method of a built-in object type, bound to its receiver.
+pub(crate) struct BuiltinMethod {
+    name: String,
+    arity: Arity,
+    receiver: Value,
+    body: BuiltinMethodBody,
+}
+
+impl BuiltinMethod {
+    pub(crate) fn bind(
+        type_name: &str,
+        name: &str,
+        receiver: Value,
+        (arity, body): (Arity, BuiltinMethodBody),
+    ) -> Native {
+        let function = Rc::new(Self {
+            name: format!("{type_name}.{name}"),
+            arity,
+            receiver,
+            body,
+        });
+        Native::bound(function, receiver)
+    }
+}
+
+impl NativeFunc for BuiltinMethod {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, vm: &mut VM, args: Args) -> Result<Value, RuntimeError> {
+        (self.body)(vm, self.receiver, args)
+    }
+}
 
 pub struct NativeClock {}
 
 impl NativeFunc for NativeClock {
-    fn call(&self, _arg_count: usize, _args: &[Rc<RefCell<Value>>]) -> Value {
+    fn name(&self) -> &str {
+        "clock"
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::Fixed(0)
+    }
+
+    fn call(&self, _vm: &mut VM, _args: Args) -> Result<Value, RuntimeError> {
         match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(n) => Value::Number(n.as_millis() as f64),
-            Err(_) => panic!("can't get system time"),
+            Ok(n) => Ok(Value::Number(n.as_millis() as f64)),
+            Err(_) => Err(RuntimeError::new(
+                RuntimeErrorKind::Native,
+                "can't get system time",
+            )),
         }
     }
 }
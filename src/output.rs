@@ -0,0 +1,35 @@
+use std::io::{self, BufWriter, Write, stdout};
+
+/// Where the `print` statement sends its text.
+pub(crate) enum Output {
+    Writer(BufWriter<Box<dyn Write>>),
+    Callback(Box<dyn FnMut(&str)>),
+}
+
+impl Output {
+    pub(crate) fn stdout() -> Self {
+        Output::writer(stdout())
+    }
+
+    pub(crate) fn writer<W: Write + 'static>(writer: W) -> Self {
+        Output::Writer(BufWriter::new(Box::new(writer)))
+    }
+
+    /// Emits one line of program output.
+    pub(crate) fn print(&mut self, text: &str) -> io::Result<()> {
+        match self {
+            Output::Writer(writer) => writeln!(writer, "{text}"),
+            Output::Callback(callback) => {
+                callback(text);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Writer(writer) => writer.flush(),
+            Output::Callback(_) => Ok(()),
+        }
+    }
+}
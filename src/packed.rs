@@ -0,0 +1,267 @@
+//! How the VM stores values on its stack and in constant tables.
+//!
+//! By default a [`PackedValue`] is just a [`Value`]. With the `nan_boxing`
+//! feature it is squeezed into 64 bits, as in clox: numbers are stored as
+//! themselves, and everything else hides in the payload of a quiet NaN.
+//! Either way the rest of the crate only packs and unpacks through `From`.
+
+use crate::value::*;
+
+#[cfg(not(feature = "nan_boxing"))]
+mod repr {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct PackedValue(Value);
+
+    impl From<Value> for PackedValue {
+        fn from(value: Value) -> Self {
+            Self(value)
+        }
+    }
+
+    impl From<PackedValue> for Value {
+        fn from(packed: PackedValue) -> Self {
+            packed.0
+        }
+    }
+}
+
+#[cfg(feature = "nan_boxing")]
+mod repr {
+    use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+    use super::*;
+    use crate::gc::*;
+
+    #[cfg(not(target_pointer_width = "64"))]
+    compile_error!("the `nan_boxing` feature needs 64-bit pointers");
+
+    const SIGN_BIT: u64 = 1 << 63;
+    const QNAN: u64 = 0x7ffc_0000_0000_0000;
+
+    const NIL: u64 = QNAN | 1;
+    const FALSE: u64 = QNAN | 2;
+    const TRUE: u64 = QNAN | 3;
+
+    // Heap objects are at least 8-byte aligned and live below 2^48, which
+    // leaves the low three bits and bits 48-49 free for the object kind.
+    const POINTER_MASK: u64 = 0x0000_ffff_ffff_fff8;
+    const KIND_LOW_MASK: u64 = 0x7;
+    const KIND_HIGH_SHIFT: u32 = 48;
+
+    const STR: u64 = 0;
+    const FUNC: u64 = 1;
+    const NATIVE: u64 = 2;
+    const CLOSURE: u64 = 3;
+    const CLASS: u64 = 4;
+    const INSTANCE: u64 = 5;
+    const BOUND: u64 = 6;
+    const FOREIGN: u64 = 7;
+    const WEAK: u64 = 8;
+    const WEAK_MAP: u64 = 9;
+    const LIST: u64 = 10;
+    const MAP: u64 = 11;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct PackedValue(u64);
+
+    const _: () = assert!(std::mem::size_of::<PackedValue>() == 8);
+
+    impl PackedValue {
+        fn object<T: Trace + 'static>(kind: u64, object: Gc<T>) -> Self {
+            let address = Gc::as_ptr(object) as u64;
+            debug_assert_eq!(address & !POINTER_MASK, 0, "pointer does not fit in a NaN box");
+            let kind_bits = (kind & KIND_LOW_MASK) | ((kind >> 3) << KIND_HIGH_SHIFT);
+            Self(SIGN_BIT | QNAN | kind_bits | address)
+        }
+
+        fn is_object(self) -> bool {
+            self.0 & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN
+        }
+
+        fn kind(self) -> u64 {
+            (self.0 & KIND_LOW_MASK) | (((self.0 >> KIND_HIGH_SHIFT) & 0x3) << 3)
+        }
+
+        /// # Safety
+        ///
+        /// `self` must hold a live object of type `T`.
+        unsafe fn as_object<T: Trace + 'static>(self) -> Gc<T> {
+            unsafe { Gc::from_ptr((self.0 & POINTER_MASK) as *const ()) }
+        }
+    }
+
+    impl From<Value> for PackedValue {
+        fn from(value: Value) -> Self {
+            match value {
+                // Keep NaNs produced by arithmetic out of the tagged space.
+                Value::Number(n) if n.is_nan() => Self(f64::NAN.to_bits()),
+                Value::Number(n) => Self(n.to_bits()),
+                Value::Nil => Self(NIL),
+                Value::Boolean(false) => Self(FALSE),
+                Value::Boolean(true) => Self(TRUE),
+                Value::Str(object) => Self::object(STR, object),
+                Value::Func(object) => Self::object(FUNC, object),
+                Value::Native(object) => Self::object(NATIVE, object),
+                Value::Closure(object) => Self::object(CLOSURE, object),
+                Value::Class(object) => Self::object(CLASS, object),
+                Value::Instance(object) => Self::object(INSTANCE, object),
+                Value::Bound(object) => Self::object(BOUND, object),
+                Value::Foreign(object) => Self::object(FOREIGN, object),
+                Value::Weak(object) => Self::object(WEAK, object),
+                Value::WeakMap(object) => Self::object(WEAK_MAP, object),
+                Value::List(object) => Self::object(LIST, object),
+                Value::Map(object) => Self::object(MAP, object),
+            }
+        }
+    }
+
+    impl From<PackedValue> for Value {
+        fn from(packed: PackedValue) -> Self {
+            if packed.0 & QNAN != QNAN {
+                return Value::Number(f64::from_bits(packed.0));
+            }
+            if !packed.is_object() {
+                return match packed.0 {
+                    NIL => Value::Nil,
+                    FALSE => Value::Boolean(false),
+                    TRUE => Value::Boolean(true),
+                    _ => Value::Number(f64::from_bits(packed.0)),
+                };
+            }
+            // SAFETY: the kind bits were written by `PackedValue::object`
+            // together with a pointer to an object of that kind.
+            unsafe {
+                match packed.kind() {
+                    STR => Value::Str(packed.as_object()),
+                    FUNC => Value::Func(packed.as_object()),
+                    NATIVE => Value::Native(packed.as_object()),
+                    CLOSURE => Value::Closure(packed.as_object()),
+                    CLASS => Value::Class(packed.as_object()),
+                    INSTANCE => Value::Instance(packed.as_object()),
+                    BOUND => Value::Bound(packed.as_object()),
+                    FOREIGN => Value::Foreign(packed.as_object()),
+                    WEAK => Value::Weak(packed.as_object()),
+                    WEAK_MAP => Value::WeakMap(packed.as_object()),
+                    LIST => Value::List(packed.as_object()),
+                    MAP => Value::Map(packed.as_object()),
+                    kind => unreachable!("unknown object kind {kind} in NaN box"),
+                }
+            }
+        }
+    }
+
+    impl Debug for PackedValue {
+        fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+            Value::from(*self).fmt(f)
+        }
+    }
+}
+
+pub(crate) use repr::PackedValue;
+
+impl PackedValue {
+    pub(crate) fn unpack(self) -> Value {
+        self.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::compliler::Compiler;
+    use crate::foreign::ForeignClass;
+    use crate::native::Arity;
+    use crate::vm::VM;
+
+    fn round_trip(value: Value) -> Value {
+        PackedValue::from(value).unpack()
+    }
+
+    fn assert_same_number(n: f64) {
+        match round_trip(Value::Number(n)) {
+            Value::Number(m) if n.is_nan() => assert!(m.is_nan(), "{n:?} came back as {m:?}"),
+            Value::Number(m) => assert_eq!(m.to_bits(), n.to_bits(), "{n:?} came back as {m:?}"),
+            other => panic!("{n:?} came back as {other:?}"),
+        }
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for n in [
+            0.0,
+            -0.0,
+            1.0,
+            -1.5,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::MAX,
+            f64::MIN,
+            f64::MIN_POSITIVE,
+            f64::EPSILON,
+            5e-324,
+            f64::NAN,
+            -f64::NAN,
+            f64::INFINITY - f64::INFINITY,
+            // NaNs whose payloads overlap the tags used for other values.
+            f64::from_bits(0x7ffc_0000_0000_0001),
+            f64::from_bits(0xfffc_0000_0000_0008),
+            f64::from_bits(0xffff_ffff_ffff_ffff),
+        ] {
+            assert_same_number(n);
+        }
+    }
+
+    #[test]
+    fn literals_round_trip() {
+        for value in [Value::Nil, Value::Boolean(true), Value::Boolean(false)] {
+            assert_eq!(round_trip(value), value);
+        }
+    }
+
+    #[test]
+    fn every_object_kind_round_trips() {
+        let mut vm = VM::new();
+        vm.set_print_callback(|_| {});
+        vm.define_native_fn("native", Arity::Fixed(0), |_, _| Ok(Value::Nil));
+        vm.interpret(
+            "var str = \"text\";
+             fun closure() {}
+             class Class { method() {} }
+             var instance = Class();
+             var bound = instance.method;
+             var weak = weakref(instance);
+             var weak_map = WeakMap();
+             var list = [1, 2];
+             var map = {\"a\": 1};",
+        )
+        .unwrap();
+        let function = Value::Func(Compiler::new(&mut vm).compile("1;").unwrap());
+        vm.pin(&function);
+        let class = Rc::new(ForeignClass::new("Thing"));
+        let foreign = vm.new_foreign(&class, 7u8);
+
+        let mut values = vec![function, foreign];
+        for name in [
+            "str", "native", "closure", "Class", "instance", "bound", "weak", "weak_map", "list",
+            "map",
+        ] {
+            values.push(vm.get_global(name).unwrap());
+        }
+        let kinds: Vec<_> = values.iter().map(std::mem::discriminant).collect();
+        for (i, kind) in kinds.iter().enumerate() {
+            assert!(!kinds[..i].contains(kind), "{:?} is tested twice", values[i]);
+        }
+        assert_eq!(kinds.len(), 12, "every object kind is covered");
+
+        for value in values {
+            let unpacked = round_trip(value);
+            assert_eq!(unpacked, value);
+            assert!(vm.is_alive(&unpacked), "{value:?} came back as a dead handle");
+            assert_eq!(unpacked.show().to_string(), value.show().to_string());
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::diagnostic::*;
+use crate::error::*;
+use crate::span::*;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// A marked region of source with an optional message.
+///
+/// The primary label is underlined with `^` and points at the cause of the
+/// report; secondary labels use `-` and give context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+    /// The source `span` points into, if not the one being rendered.
+    pub source: Option<Rc<str>>,
+}
+
+impl Label {
+    pub fn primary<T: Into<String>>(span: Span, message: T) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            primary: true,
+            source: None,
+        }
+    }
+
+    pub fn secondary<T: Into<String>>(span: Span, message: T) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            primary: false,
+            source: None,
+        }
+    }
+
+    /// Points the label into `source` rather than the renderer's source,
+    /// for spans from an earlier input such as a previous REPL line.
+    pub fn with_source(mut self, source: Rc<str>) -> Self {
+        self.source = Some(source);
+        self
+    }
+}
+
+/// Everything the [`Renderer`] needs to print one error or warning.
+///
+/// Build one from a [`Diagnostic`] or a [`RuntimeError`] with `From`, or by
+/// hand for host-side errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Report {
+    pub fn new<T: Into<String>>(severity: Severity, message: T) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_code<T: Into<String>>(mut self, code: T) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note<T: Into<String>>(mut self, note: T) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl From<&Diagnostic> for Report {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        let label = match &diagnostic.site {
+            ErrorSite::End => "at end of input",
+            _ => "",
+        };
+        Report::new(diagnostic.severity, diagnostic.message.clone())
+            .with_code(diagnostic.code.as_str())
+            .with_label(Label::primary(diagnostic.span, label))
+    }
+}
+
+impl From<&RuntimeError> for Report {
+    fn from(error: &RuntimeError) -> Self {
+        let mut report = Report::new(Severity::Error, error.message.clone());
+        for (depth, frame) in error.trace.iter().enumerate() {
+            // Native frames have no source to point at.
+            if frame.span.line == 0 {
+                continue;
+            }
+            let message = if depth == 0 {
+                String::new()
+            } else {
+                let callee = &error.trace[depth - 1].function;
+                format!("in call to {callee} from {}", frame.function)
+            };
+            let mut label = if report.labels.is_empty() {
+                Label::primary(frame.span, message)
+            } else {
+                Label::secondary(frame.span, message)
+            };
+            if let Some(source) = &frame.source {
+                label = label.with_source(Rc::clone(source));
+            }
+            report = report.with_label(label);
+        }
+        report
+    }
+}
+
+/// Formats [`Report`]s against the source they refer to, printing each
+/// labelled line with an underline beneath the marked region.
+#[derive(Clone)]
+pub struct Renderer<'a> {
+    source: &'a str,
+    name: Option<String>,
+    color: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            name: None,
+            color: false,
+        }
+    }
+
+    /// Names the source, usually after the file it was read from.
+    pub fn with_name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Enables ANSI colour codes in the output.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn render(&self, report: &Report) -> String {
+        let mut out = String::new();
+        let (severity, severity_color) = match report.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+        out.push_str(&self.paint(severity_color, severity));
+        if let Some(code) = &report.code {
+            out.push_str(&self.paint(severity_color, &format!("[{code}]")));
+        }
+        let header = format!(": {}", report.message);
+        out.push_str(&self.paint(BOLD, &header));
+        out.push('\n');
+
+        // Labels that don't fit their source are left out rather than
+        // rendered wrongly.
+        let labels: Vec<(&Label, Located)> = report
+            .labels
+            .iter()
+            .filter(|label| label.span.line > 0)
+            .filter_map(|label| Some((label, self.locate(label)?)))
+            .collect();
+        let width = labels
+            .iter()
+            .map(|(label, _)| label.span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(width);
+
+        if let Some((first, _)) = labels.first() {
+            let name = self.name.as_deref().unwrap_or("<input>");
+            let _ = writeln!(
+                out,
+                "{gutter}{} {name}:{}:{}",
+                self.paint(BLUE, "-->"),
+                first.span.line,
+                first.span.column
+            );
+            let _ = writeln!(out, "{gutter} {}", self.paint(BLUE, "|"));
+        }
+
+        for (label, (text, prefix, marked)) in &labels {
+            let line_number = format!("{:>width$}", label.span.line);
+            let _ = writeln!(
+                out,
+                "{} {text}",
+                self.paint(BLUE, &format!("{line_number} |"))
+            );
+
+            let (mark, color) = if label.primary {
+                ('^', severity_color)
+            } else {
+                ('-', BLUE)
+            };
+            let underline = mark.to_string().repeat((*marked).max(1));
+            let mut annotation = underline;
+            if !label.message.is_empty() {
+                annotation.push(' ');
+                annotation.push_str(&label.message);
+            }
+            let _ = writeln!(
+                out,
+                "{gutter} {} {}{}",
+                self.paint(BLUE, "|"),
+                " ".repeat(*prefix),
+                self.paint(color, &annotation)
+            );
+        }
+
+        for note in &report.notes {
+            let _ = writeln!(out, "{gutter} {} note: {note}", self.paint(BLUE, "="));
+        }
+        out
+    }
+
+    /// Returns the line containing the label's span, the display width
+    /// before the span starts, and the display width of the part of the
+    /// span on that line. Returns `None` if the span does not start inside
+    /// the source on a character boundary.
+    fn locate(&self, label: &Label) -> Option<Located> {
+        let source = label.source.as_deref().unwrap_or(self.source);
+        let start = label.span.start;
+        if !source.is_char_boundary(start) {
+            return None;
+        }
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let mut end = label.span.end.clamp(start, line_end);
+        while !source.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        let prefix = display_width(&source[line_start..start]);
+        let marked = display_width(&source[start..end]);
+        Some((line.replace('\t', "    "), prefix, marked))
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{color}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// A label's line, and the display widths before and under its span.
+type Located = (String, usize, usize);
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
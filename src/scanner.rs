@@ -1,3 +1,4 @@
+use crate::span::*;
 use crate::token::*;
 use crate::token_type::*;
 
@@ -6,6 +7,11 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize,
+    start_byte: usize,
+    current_byte: usize,
+    start_line: usize,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -15,6 +21,11 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_byte: 0,
+            current_byte: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
@@ -22,6 +33,9 @@ impl Scanner {
         self.skip_whitespace();
 
         self.start = self.current;
+        self.start_byte = self.current_byte;
+        self.start_line = self.line;
+        self.start_column = self.current - self.line_start + 1;
 
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
@@ -33,13 +47,27 @@ impl Scanner {
             ')' => self.make_token(TokenType::RightParen),
             '{' => self.make_token(TokenType::LeftBrace),
             '}' => self.make_token(TokenType::RightBrace),
+            '[' => self.make_token(TokenType::LeftBracket),
+            ']' => self.make_token(TokenType::RightBracket),
             ';' => self.make_token(TokenType::SemiColon),
             ',' => self.make_token(TokenType::Comma),
+            ':' => self.make_token(TokenType::Colon),
             '.' => self.make_token(TokenType::Dot),
             '-' => self.make_token(TokenType::Minus),
             '+' => self.make_token(TokenType::Plus),
             '/' => self.make_token(TokenType::Slash),
-            '*' => self.make_token(TokenType::Star),
+            '%' => self.make_token(TokenType::Percent),
+            '*' => match self.is_match('*') {
+                true => self.make_token(TokenType::StarStar),
+                false => self.make_token(TokenType::Star),
+            },
+            '&' => self.make_token(TokenType::Ampersand),
+            '|' => self.make_token(TokenType::Pipe),
+            '^' => self.make_token(TokenType::Caret),
+            '~' => match self.is_match('/') {
+                true => self.make_token(TokenType::TildeSlash),
+                false => self.make_token(TokenType::Tilde),
+            },
             '!' => match self.is_match('=') {
                 true => self.make_token(TokenType::BangEqual),
                 false => self.make_token(TokenType::Bang),
@@ -48,10 +76,12 @@ impl Scanner {
                 true => self.make_token(TokenType::Equal),
                 false => self.make_token(TokenType::Assign),
             },
+            '>' if self.is_match('>') => self.make_token(TokenType::GreaterGreater),
             '>' => match self.is_match('=') {
                 true => self.make_token(TokenType::GreaterEqual),
                 false => self.make_token(TokenType::Greater),
             },
+            '<' if self.is_match('<') => self.make_token(TokenType::LessLess),
             '<' => match self.is_match('=') {
                 true => self.make_token(TokenType::LessEqual),
                 false => self.make_token(TokenType::Less),
@@ -63,6 +93,32 @@ impl Scanner {
         }
     }
 
+    /// Returns the type of the next token without consuming it.
+    pub fn peek_token(&mut self) -> TokenType {
+        let saved = (
+            self.start,
+            self.current,
+            self.line,
+            self.line_start,
+            self.start_byte,
+            self.current_byte,
+            self.start_line,
+            self.start_column,
+        );
+        let ttype = self.scan_token().ttype;
+        (
+            self.start,
+            self.current,
+            self.line,
+            self.line_start,
+            self.start_byte,
+            self.current_byte,
+            self.start_line,
+            self.start_column,
+        ) = saved;
+        ttype
+    }
+
     fn identifier(&mut self) -> Token {
         while self.peek_is_alphanumeric() {
             self.advance();
@@ -79,7 +135,18 @@ impl Scanner {
     fn identifier_type(&self) -> TokenType {
         match self.source[self.start] {
             'a' => self.check_keyword(1, 2, "nd", TokenType::And),
-            'c' => self.check_keyword(1, 4, "lass", TokenType::Class),
+            'b' => self.check_keyword(1, 4, "reak", TokenType::Break),
+            'c' => {
+                if self.current - self.start > 1 {
+                    match self.source[self.start + 1] {
+                        'l' => self.check_keyword(2, 3, "ass", TokenType::Class),
+                        'o' => self.check_keyword(2, 6, "ntinue", TokenType::Continue),
+                        _ => TokenType::Identifier,
+                    }
+                } else {
+                    TokenType::Identifier
+                }
+            }
             'e' => self.check_keyword(1, 3, "lse", TokenType::Else),
             'f' => {
                 if self.current - self.start > 1 {
@@ -151,8 +218,11 @@ impl Scanner {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.advance();
+                self.line_start = self.current;
+            } else {
+                self.advance();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
@@ -172,6 +242,7 @@ impl Scanner {
             ttype,
             lexeme: self.source[self.start..self.current].iter().collect(),
             line: self.line,
+            span: self.span(),
         }
     }
 
@@ -180,12 +251,24 @@ impl Scanner {
             ttype: TokenType::Error,
             lexeme: message.to_string(),
             line: self.line,
+            span: self.span(),
         }
     }
 
+    fn span(&self) -> Span {
+        Span::new(
+            self.start_byte,
+            self.current_byte,
+            self.start_line,
+            self.start_column,
+        )
+    }
+
     fn advance(&mut self) -> char {
         self.current += 1;
-        self.source[self.current - 1]
+        let c = self.source[self.current - 1];
+        self.current_byte += c.len_utf8();
+        c
     }
 
     fn is_match(&mut self, expected: char) -> bool {
@@ -195,7 +278,7 @@ impl Scanner {
         if self.source[self.current] != expected {
             return false;
         }
-        self.current += 1;
+        self.advance();
         true
     }
 
@@ -208,6 +291,7 @@ impl Scanner {
                 '\n' => {
                     self.line += 1;
                     self.advance();
+                    self.line_start = self.current;
                 }
                 '/' => {
                     if let Some('/') = self.peek_next() {
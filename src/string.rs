@@ -0,0 +1,93 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::hash::{Hash, Hasher};
+
+use crate::gc::*;
+
+/// An immutable, interned Lox string.
+///
+/// The VM keeps at most one `Str` per distinct text, so two string values
+/// are equal exactly when their handles are.
+pub struct Str {
+    text: Box<str>,
+}
+
+impl Str {
+    pub(crate) fn new(text: &str) -> Self {
+        Self { text: text.into() }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Trace for Str {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl HeapObject for Str {
+    const KIND: ObjectKind = ObjectKind::String;
+
+    fn payload_size(&self) -> usize {
+        self.text.len()
+    }
+}
+
+impl Debug for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        Debug::fmt(&self.text, f)
+    }
+}
+
+impl Display for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.text)
+    }
+}
+
+// Entry in the intern table, compared by contents so that it can be
+// looked up by `&str`.
+struct Interned(Gc<Str>);
+
+impl PartialEq for Interned {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.get().as_str() == other.0.get().as_str()
+    }
+}
+
+impl Eq for Interned {}
+
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.get().as_str().hash(state)
+    }
+}
+
+impl Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        self.0.get().as_str()
+    }
+}
+
+/// The set of live strings. Entries do not keep their strings alive; the
+/// heap drops the ones it is about to free.
+#[derive(Default)]
+pub(crate) struct Interner {
+    strings: HashSet<Interned>,
+}
+
+impl Interner {
+    pub(crate) fn get(&self, text: &str) -> Option<Gc<Str>> {
+        self.strings.get(text).map(|interned| interned.0)
+    }
+
+    pub(crate) fn insert(&mut self, string: Gc<Str>) {
+        self.strings.insert(Interned(string));
+    }
+
+    pub(crate) fn retain<F: Fn(Gc<Str>) -> bool>(&mut self, keep: F) {
+        self.strings.retain(|interned| keep(interned.0));
+    }
+}
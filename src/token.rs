@@ -1,3 +1,4 @@
+use crate::span::*;
 use crate::token_type::*;
 
 #[derive(Debug, PartialEq, Eq, Hash, )]
@@ -5,6 +6,7 @@ pub struct Token {
     pub ttype: TokenType,
     pub lexeme: String,
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
@@ -12,7 +14,8 @@ impl Token {
         Self { 
             ttype: TokenType::Undefined, 
             lexeme: lexeme.to_string(), 
-            line: 0
+            line: 0,
+            span: Span::default(),
         }
     }
 }
@@ -23,6 +26,7 @@ impl Default for Token {
             ttype: TokenType::Undefined,
             lexeme: String::new(),
             line: 0,
+            span: Span::default(),
         }
     }
 }
@@ -33,6 +37,7 @@ impl Clone for Token {
             ttype: self.ttype,
             lexeme: self.lexeme.clone(),
             line: self.line,
+            span: self.span,
         }
     }
 }
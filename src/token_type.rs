@@ -5,13 +5,21 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     SemiColon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
     // One or two character tokens.
     Bang,
     BangEqual,
@@ -21,15 +29,20 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    StarStar,
+    TildeSlash,
+    LessLess,
+    GreaterGreater,
     // Literals.
     Identifier,
     String,
     Number,
 
     // Keywords.
-    //Break,
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -1,26 +1,70 @@
-use std::cell::RefCell;
-use std::ops::Deref;
-use std::rc::Rc;
+use std::cell::Cell;
 
-use crate::value::*;
+use crate::gc::*;
+use crate::packed::*;
 
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// The variable still lives in this stack slot.
+    Open(usize),
+    /// The variable's frame has returned; the upvalue now owns it.
+    Closed(PackedValue),
+}
+
+/// A variable captured by a closure.
+///
+/// While the enclosing function runs, the upvalue points into the VM
+/// stack, so the closure and the function share the variable. Once the
+/// variable goes out of scope it is closed over: its value moves into the
+/// upvalue itself.
 #[derive(Debug)]
 pub struct Upvalue {
-    location: Rc<RefCell<Value>>,
+    state: Cell<State>,
 }
 
 impl Upvalue {
-    pub fn new(value: &Rc<RefCell<Value>>) -> Self {
+    pub(crate) fn new(slot: usize) -> Self {
         Self {
-            location: Rc::clone(value),
+            state: Cell::new(State::Open(slot)),
+        }
+    }
+
+    /// The stack slot the upvalue points at, if it is still open.
+    pub(crate) fn slot(&self) -> Option<usize> {
+        match self.state.get() {
+            State::Open(slot) => Some(slot),
+            State::Closed(_) => None,
         }
     }
 
-    pub fn value(&self) -> Rc<RefCell<Value>> {
-        self.location.clone()
+    pub(crate) fn get(&self, stack: &[PackedValue]) -> PackedValue {
+        match self.state.get() {
+            State::Open(slot) => stack[slot],
+            State::Closed(value) => value,
+        }
     }
 
-    pub fn set(&self, value: &Rc<RefCell<Value>>) {
-        *self.location.borrow_mut() = value.borrow().deref().clone()
+    pub(crate) fn set(&self, stack: &mut [PackedValue], value: PackedValue) {
+        match self.state.get() {
+            State::Open(slot) => stack[slot] = value,
+            State::Closed(_) => self.state.set(State::Closed(value)),
+        }
     }
+
+    /// Moves the variable off the stack and into the upvalue.
+    pub(crate) fn close(&self, stack: &[PackedValue]) {
+        self.state.set(State::Closed(self.get(stack)));
+    }
+}
+
+impl Trace for Upvalue {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let State::Closed(value) = self.state.get() {
+            tracer.mark_value(&value.unpack());
+        }
+    }
+}
+
+impl HeapObject for Upvalue {
+    const KIND: ObjectKind = ObjectKind::Upvalue;
 }
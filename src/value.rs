@@ -1,32 +1,41 @@
-use std::any::Any;
 use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::fmt::{Debug, Display, Result};
+use std::fmt::{Display, Formatter, Result};
 use std::ops::{Add, Div, Mul, Neg, Sub};
-use std::rc::Rc;
 
 use crate::closure::*;
 use crate::function::*;
 use crate::class::*;
 use crate::instance::*;
 use crate::bound_method::*;
+use crate::native::*;
+use crate::packed::*;
+use crate::foreign::*;
+use crate::gc::*;
+use crate::string::*;
+use crate::weak::*;
+use crate::list::*;
+use crate::map::*;
+use crate::vm::VM;
 
-pub trait NativeFunc {
-    fn call(&self, arg_count: usize, args: &[Rc<RefCell<Value>>]) -> Value;
-}
-
-#[derive(Debug)]
+/// A Lox runtime value.
+#[derive(Debug, Clone, Copy)]
 pub enum Value {
     Boolean(bool),
     Number(f64),
     Nil,
-    Str(String),
-    Func(Rc<Function>),
-    Native(Rc<dyn NativeFunc>),
-    Closure(Rc<Closure>),
-    Class(Rc<Class>),
-    Instance(Rc<Instance>),
-    Bound(Rc<BoundMethod>)
+    Str(Gc<Str>),
+    Func(Gc<Function>),
+    Native(Gc<Native>),
+    Closure(Gc<Closure>),
+    Class(Gc<Class>),
+    Instance(Gc<Instance>),
+    Bound(Gc<BoundMethod>),
+    Foreign(Gc<Foreign>),
+    Weak(Gc<WeakRef>),
+    WeakMap(Gc<WeakMap>),
+    List(Gc<List>),
+    Map(Gc<Map>),
 }
 
 impl PartialEq for Value {
@@ -34,67 +43,94 @@ impl PartialEq for Value {
         match (self, other) {
             (Value::Boolean(a), Value::Boolean(b)) => a.eq(b),
             (Value::Number(a), Value::Number(b)) => a.eq(b),
-            (Value::Str(a), Value::Str(b)) => a.cmp(b) == Ordering::Equal,
+            (Value::Str(a), Value::Str(b)) => Gc::ptr_eq(a, b),
             (Value::Nil, Value::Nil) => true,
-            (Value::Func(a), Value::Func(b)) => Rc::ptr_eq(a, b),
-            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
-            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
-            (Value::Native(a), Value::Native(b)) => a.type_id() == b.type_id(),
-            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
-            (Value::Bound(a), Value::Bound(b)) => Rc::ptr_eq(a, b),
+            (Value::Func(a), Value::Func(b)) => Gc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Gc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Gc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Gc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Gc::ptr_eq(a, b),
+            (Value::Bound(a), Value::Bound(b)) => Gc::ptr_eq(a, b),
+            (Value::Foreign(a), Value::Foreign(b)) => Gc::ptr_eq(a, b),
+            (Value::Weak(a), Value::Weak(b)) => Gc::ptr_eq(a, b),
+            (Value::WeakMap(a), Value::WeakMap(b)) => Gc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Gc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Gc::ptr_eq(a, b),
             _ => false,
         }
     }
 }
 
-impl PartialOrd for Value {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
-            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
-            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),            
-            _ => None,
-        }
-    }
+/// Formats a [`Value`] the way `print` does; see [`Value::display`].
+pub struct ValueDisplay<'a> {
+    value: &'a Value,
+    // The VM to check the value against. Values inside the crate come from
+    // the VM's own roots and need no check.
+    vm: Option<&'a VM>,
 }
 
-impl Debug for dyn NativeFunc {
+impl Display for ValueDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result {
-        write!(f, "<native fn")
+        if let Some(vm) = self.vm
+            && !vm.is_alive(self.value)
+        {
+            return write!(f, "<dead object>");
+        }
+        match self.value {
+            Value::Boolean(t) => write!(f, "{t}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Nil => write!(f, "nil"),
+            Value::Str(s) => write!(f, "{}", s.get()),
+            Value::Func(func) => write!(f, "{}", func.get()),
+            Value::Native(native) => write!(f, "{}", native.get()),
+            Value::Closure(c) => write!(f, "{}", c.get()),
+            Value::Class(klass) => write!(f, "{}", klass.get()),
+            Value::Instance(i) => write!(f, "{}", i.get()),
+            Value::Bound(b) => write!(f, "{}", b.get()),
+            Value::Foreign(o) => write!(f, "{}", o.get()),
+            Value::Weak(w) => write!(f, "{}", w.get()),
+            Value::WeakMap(m) => write!(f, "{}", m.get()),
+            Value::List(l) => write!(f, "{}", l.get()),
+            Value::Map(m) => write!(f, "{}", m.get()),
+        }
     }
 }
 
-impl Clone for Value {
-    fn clone(&self) -> Self {
-        match self {
-            Value::Boolean(b) => Value::Boolean(*b),
-            Value::Number(n) => Value::Number(*n),
-            Value::Nil => Value::Nil,
-            Value::Str(s) => Value::Str(s.clone()),
-            Value::Func(f) => Value::Func(Rc::clone(f)),
-            Value::Native(f) => Value::Native(Rc::clone(f)),
-            Value::Closure(f) => Value::Closure(Rc::clone(f)),
-            Value::Class(c) => Value::Class(Rc::clone(c)),
-            Value::Instance(i) => Value::Instance(Rc::clone(i)),
-            Value::Bound(b) => Value::Bound(Rc::clone(b))
+thread_local! {
+    // Lists and maps being formatted further up the stack.
+    static FORMATTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Formats the list or map at `object` with `body`, unless it is already
+/// being formatted further up, in which case `placeholder` is written
+/// instead. This stops cycles through any mix of lists and maps from
+/// recursing forever.
+pub(crate) fn format_container<T>(
+    object: &T,
+    placeholder: &str,
+    f: &mut Formatter<'_>,
+    body: impl FnOnce(&mut Formatter<'_>) -> Result,
+) -> Result {
+    struct Entered;
+
+    impl Drop for Entered {
+        fn drop(&mut self) {
+            FORMATTING.with(|formatting| formatting.borrow_mut().pop());
         }
     }
-}
 
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result {
-        match self {
-            Value::Boolean(t) => write!(f, "{t}"),
-            Value::Number(n) => write!(f, "{n}"),
-            Value::Nil => write!(f, "nil"),
-            Value::Str(s) => write!(f, "{s}"),
-            Value::Func(func) => write!(f, "{}", func),
-            Value::Native(_) => write!(f, "<native fn>"),
-            Value::Closure(c) => write!(f, "{c}"),
-            Value::Class(klass) => write!(f, "{}", klass),
-            Value::Instance(i) => write!(f, "{i}"),
-            Value::Bound(b) => write!(f, "{b}")
+    let object = object as *const T as *const ();
+    let entered = FORMATTING.with(|formatting| {
+        let mut formatting = formatting.borrow_mut();
+        if formatting.contains(&object) {
+            return None;
         }
+        formatting.push(object);
+        Some(Entered)
+    });
+    match entered {
+        Some(_entered) => body(f),
+        None => f.write_str(placeholder),
     }
 }
 
@@ -103,7 +139,6 @@ impl Add for Value {
     fn add(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
             (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
-            (Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
             _ => panic!("Invalid operations"),
         }
     }
@@ -150,6 +185,33 @@ impl Neg for Value {
 }
 
 impl Value {
+    /// Formats `self` the way `print` does. An object that `vm` has freed,
+    /// or that belongs to another VM, shows as `<dead object>`.
+    pub fn display<'a>(&'a self, vm: &'a VM) -> ValueDisplay<'a> {
+        ValueDisplay {
+            value: self,
+            vm: Some(vm),
+        }
+    }
+
+    /// Formats a value the VM knows to be alive.
+    pub(crate) fn show(&self) -> ValueDisplay<'_> {
+        ValueDisplay {
+            value: self,
+            vm: None,
+        }
+    }
+
+    /// Orders two numbers or two strings; other values are unordered.
+    pub(crate) fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.get().as_str().partial_cmp(b.get().as_str()),
+            _ => None,
+        }
+    }
+
     pub fn is_number(&self) -> bool {
         matches!(self, Value::Number(_))
     }
@@ -158,6 +220,26 @@ impl Value {
         matches!(self, Value::Str(_))
     }
 
+    /// Identifies the object this value refers to, or `None` for numbers,
+    /// booleans and `nil`.
+    pub(crate) fn object_id(&self) -> Option<usize> {
+        match self {
+            Value::Str(o) => Some(Gc::addr(*o)),
+            Value::Func(o) => Some(Gc::addr(*o)),
+            Value::Native(o) => Some(Gc::addr(*o)),
+            Value::Closure(o) => Some(Gc::addr(*o)),
+            Value::Class(o) => Some(Gc::addr(*o)),
+            Value::Instance(o) => Some(Gc::addr(*o)),
+            Value::Bound(o) => Some(Gc::addr(*o)),
+            Value::Foreign(o) => Some(Gc::addr(*o)),
+            Value::Weak(o) => Some(Gc::addr(*o)),
+            Value::WeakMap(o) => Some(Gc::addr(*o)),
+            Value::List(o) => Some(Gc::addr(*o)),
+            Value::Map(o) => Some(Gc::addr(*o)),
+            Value::Boolean(_) | Value::Number(_) | Value::Nil => None,
+        }
+    }
+
     pub fn is_falsey(&self) -> bool {
         matches!(self, Value::Nil | Value::Boolean(false))
     }
@@ -165,7 +247,7 @@ impl Value {
 
 #[derive(Clone, Debug, Default)]
 pub struct ValueArray {
-    values: Vec<Value>,
+    values: Vec<PackedValue>,
 }
 
 impl ValueArray {
@@ -175,16 +257,20 @@ impl ValueArray {
 
     pub fn write(&mut self, value: Value) -> usize {
         let count = self.values.len();
-        self.values.push(value);
+        self.values.push(value.into());
         count
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = Value> {
+        self.values.iter().map(|value| value.unpack())
+    }
+
     #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
-    pub fn print_value(&self, which: usize) {
-        print!("{}", self.values[which])
+    pub fn write_value(&self, out: &mut dyn std::io::Write, which: usize) -> std::io::Result<()> {
+        write!(out, "{}", self.read_value(which).show())
     }
 
-    pub fn read_value(&self, which: usize) -> &Value {
-        &self.values[which]
+    pub fn read_value(&self, which: usize) -> Value {
+        self.values[which].unpack()
     }
 }
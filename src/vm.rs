@@ -1,27 +1,91 @@
+use std::any::Any;
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
-use std::ops::Deref;
+use std::io::Write;
 use std::rc::Rc;
+use std::time::Instant;
 
 use crate::chunks::*;
 use crate::closure::*;
 use crate::compliler::*;
 use crate::error::*;
+use crate::foreign::*;
+use crate::gc::*;
 use crate::native::*;
+use crate::output::*;
+use crate::packed::*;
+use crate::span::*;
+use crate::string::*;
 use crate::value::*;
 use crate::class::*;
 use crate::instance::*;
+use crate::limits::*;
+use crate::list::*;
+use crate::map::*;
 use crate::bound_method::*;
+use crate::upvalue::*;
+use crate::weak::*;
 
+// Reading the clock on every instruction is too slow, so the deadline is
+// only checked this often.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+type FinalizerErrorHandler = dyn FnMut(&RuntimeError);
+
+const SHIFT_ERROR: &str = "Shift count must be between 0 and 63.";
+
+/// The value of `value` as a 64-bit integer, if it is a whole number in
+/// that range. Bitwise operators work on these.
+fn as_integer(value: Value) -> Option<i64> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    match value {
+        Value::Number(n) if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) => Some(n as i64),
+        _ => None,
+    }
+}
+
+/// The Lox virtual machine.
+///
+/// A `VM` keeps its globals between calls to [`VM::interpret`], so a host
+/// can run several snippets against the same environment, as the REPL does.
 pub struct VM {
-    stack: Vec<Rc<RefCell<Value>>>,
+    stack: Vec<PackedValue>,
     frames: Vec<CallFrame>,
-    globals: HashMap<String, Value>,
+    // Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Gc<Upvalue>>,
+    globals: HashMap<Gc<Str>, Value>,
+    output: Output,
+    #[cfg(feature = "debug_trace_execution")]
+    trace: Box<dyn Write>,
+    // Frames and stack slots below these belong to an outer `run` that is
+    // waiting on a call made from Rust.
+    frame_base: usize,
+    stack_base: usize,
+    // Calls into `enter` that have not returned; zero while the host has
+    // control.
+    entered: usize,
+    limits: Limits,
+    executed: u64,
+    deadline: Option<Instant>,
+    interrupt: InterruptHandle,
+    heap: Heap,
+    // Values the host asked to keep alive across collections.
+    pinned: Vec<Value>,
+    init_string: Gc<Str>,
+    deinit_string: Gc<Str>,
+    // Dead instances waiting for their `deinit` to run, oldest last.
+    finalizers: Vec<Gc<Instance>>,
+    finalizing: bool,
+    // Set while the VM is being dropped; instances created then are not
+    // registered for finalizing.
+    shutting_down: bool,
+    finalizer_error_handler: Option<Box<FinalizerErrorHandler>>,
 }
 
 struct CallFrame {
-    closure: Rc<Closure>,
+    closure: Gc<Closure>,
     ip: RefCell<usize>,
     slots: usize,
 }
@@ -36,34 +100,413 @@ impl CallFrame {
     }
 }
 
+impl Drop for VM {
+    /// Runs the `deinit` of every instance that still has one to run:
+    /// first those a collection already found dead, oldest first, then
+    /// those still reachable, in the order they were created.
+    ///
+    /// Instances created by these finalizers are not finalized. Errors go
+    /// to the finalizer error handler; running out of instructions or time,
+    /// or being interrupted, skips the finalizers that have not run yet.
+    fn drop(&mut self) {
+        // Dropped while unwinding out of a call: the stack can't be trusted.
+        if self.entered > 0 {
+            return;
+        }
+        self.shutting_down = true;
+        let remaining = self.heap.take_finalizable();
+        self.finalizers.splice(0..0, remaining.into_iter().rev());
+        if let Err(error) = self.run_finalizers() {
+            self.report_finalizer_error(&error);
+        }
+        let _ = self.output.flush();
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VM {
+    /// Creates a VM with the built-in natives (`clock`, `weakref`, `deref`
+    /// and `WeakMap`) already defined.
     pub fn new() -> Self {
+        let mut heap = Heap::new();
+        let init_string = heap.intern("init");
+        let deinit_string = heap.intern("deinit");
         let mut vm = Self {
             stack: Vec::new(),
             frames: Vec::new(),
+            open_upvalues: Vec::new(),
             globals: HashMap::new(),
+            output: Output::stdout(),
+            #[cfg(feature = "debug_trace_execution")]
+            trace: Box::new(std::io::stdout()),
+            frame_base: 0,
+            stack_base: 0,
+            entered: 0,
+            limits: Limits::default(),
+            executed: 0,
+            deadline: None,
+            interrupt: InterruptHandle::default(),
+            heap,
+            pinned: Vec::new(),
+            init_string,
+            deinit_string,
+            finalizers: Vec::new(),
+            finalizing: false,
+            shutting_down: false,
+            finalizer_error_handler: None,
         };
         let f: Rc<dyn NativeFunc> = Rc::new(NativeClock {});
         vm.define_native("clock", &f);
+        vm.define_native_fn("weakref", Arity::Fixed(1), weakref);
+        vm.define_native_fn("deref", Arity::Fixed(1), deref);
+        vm.define_native_fn("WeakMap", Arity::Fixed(0), weak_map);
         vm
     }
 
-    pub fn reset_stack(&mut self) {
-        self.stack.clear();
+    /// Compiles `source` and runs it as a top-level script.
+    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretResult> {
+        let mut compiler = Compiler::with_heap(&mut self.heap);
+        let function = compiler
+            .compile(source)
+            .map_err(InterpretResult::CompileError)?;
+
+        let closure = self.heap.alloc(Closure::new(function, Vec::new()));
+        self.call_function(&Value::Closure(closure), &[])
+            .map_err(InterpretResult::RuntimeError)?;
+        Ok(())
+    }
+
+    /// Calls a Lox function, class or native with `args` and returns its
+    /// result.
+    ///
+    /// This may be used while a script is running, for example from inside
+    /// a native function; the script resumes once the call returns. An
+    /// error unwinds only the frames created by this call, and its trace
+    /// covers just those frames.
+    pub fn call_function(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.check_handles(std::iter::once(callee).chain(args))?;
+        let stack_base = self.stack.len();
+        self.push(*callee);
+        for arg in args {
+            self.push(*arg);
+        }
+        self.enter(stack_base, |vm| vm.call_value(args.len()))
     }
 
-    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretResult> {
-        let mut compiler = Compiler::new();
-        let function = compiler.compile(source)?;
-
-        let closure = Rc::new(Closure::new(Rc::new(function)));
-        self.stack.push(Rc::new(RefCell::new(Value::Closure(Rc::clone(&closure)))));
-        self.call(closure, 0);
-        let result = self.run();
-        self.stack.pop();
+    /// Calls the global function `name` with `args`.
+    pub fn call_global(&mut self, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+        match self.get_global(name) {
+            Some(callee) => self.call_function(&callee, args),
+            None => Err(RuntimeError::new(
+                RuntimeErrorKind::UndefinedVariable,
+                format!("Undefined variable '{name}'"),
+            )),
+        }
+    }
+
+    /// Calls the method `name` on `receiver`, which must be an instance.
+    /// A field holding a callable is called in preference to a method, as
+    /// `receiver.name(args)` would in Lox.
+    pub fn invoke_method(
+        &mut self,
+        receiver: &Value,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        self.check_handles(std::iter::once(receiver).chain(args))?;
+        let name = self.heap.intern(name);
+        let stack_base = self.stack.len();
+        self.push(*receiver);
+        for arg in args {
+            self.push(*arg);
+        }
+        self.enter(stack_base, |vm| vm.invoke(name, args.len()))
+    }
+
+    // Handles from the host may have outlived their objects, so they are
+    // checked before the VM uses them.
+    fn check_handles<'a, I>(&self, values: I) -> Result<(), RuntimeError>
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        if values.into_iter().all(|value| self.is_alive(value)) {
+            Ok(())
+        } else {
+            Err(RuntimeError::new(
+                RuntimeErrorKind::InvalidHandle,
+                "A value from the host has been collected or belongs to another VM.",
+            ))
+        }
+    }
+
+    /// Runs a call set up by `start` to completion and pops its result,
+    /// treating frames and slots above the current ones as a nested run.
+    fn enter<F>(&mut self, stack_base: usize, start: F) -> Result<Value, RuntimeError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), InterpretResult>,
+    {
+        if self.entered == 0 {
+            self.executed = 0;
+            self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        }
+        let saved = (self.frame_base, self.stack_base);
+        self.frame_base = self.frames.len();
+        self.stack_base = stack_base;
+        self.entered += 1;
+
+        let mut result = start(self);
+        if result.is_ok() && self.frames.len() > self.frame_base {
+            result = self.run();
+        }
+        let mut result = match result {
+            Ok(()) => Ok(self.pop()),
+            Err(InterpretResult::RuntimeError(error)) => Err(error),
+            Err(_) => unreachable!("only runtime errors are raised while running"),
+        };
+
+        (self.frame_base, self.stack_base) = saved;
+        self.entered -= 1;
+        if self.entered == 0 {
+            // Back with the host: an interrupt has stopped everything it
+            // was meant to. A native called straight from the host may have
+            // swallowed the errors, so the call still counts as stopped.
+            if self.interrupt.is_interrupted() {
+                self.interrupt.clear();
+                if result.is_ok() {
+                    result = Err(RuntimeError::new(RuntimeErrorKind::Interrupted, "Interrupted."));
+                }
+            }
+            if let Err(e) = self.output.flush()
+                && result.is_ok()
+            {
+                result = Err(RuntimeError::new(RuntimeErrorKind::Io, e.to_string()));
+            }
+        }
+        result
+    }
+
+    /// Sends `print` output to `writer` instead of stdout.
+    ///
+    /// Output is buffered and flushed when control returns to the host from
+    /// [`VM::interpret`] or a call into Lox, or earlier with
+    /// [`VM::flush_output`].
+    pub fn set_output<W: Write + 'static>(&mut self, writer: W) {
+        let _ = self.output.flush();
+        self.output = Output::writer(writer);
+    }
+
+    /// Hands the text of every `print` statement to `callback`, without the
+    /// trailing newline.
+    pub fn set_print_callback<F: FnMut(&str) + 'static>(&mut self, callback: F) {
+        let _ = self.output.flush();
+        self.output = Output::Callback(Box::new(callback));
+    }
+
+    pub fn flush_output(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+
+    /// Sends the execution trace to `writer` instead of stdout.
+    #[cfg(feature = "debug_trace_execution")]
+    pub fn set_trace_output<W: Write + 'static>(&mut self, writer: W) {
+        self.trace = Box::new(writer);
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Replaces the execution limits. They take effect from the next call
+    /// into the VM.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.heap.set_memory_limit(limits.max_memory);
+        self.limits = limits;
+    }
+
+    /// Returns a handle that stops the running script when triggered.
+    ///
+    /// The flag stays set until control returns to the host, so every
+    /// nested call that natives make back into Lox stops as well. An
+    /// interrupt sent while the VM is idle stops the next call from the
+    /// host before its first instruction. An interrupted call fails with
+    /// `Interrupted` even if a native caught the error from a nested call.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn gc_config(&self) -> &GcConfig {
+        self.heap.config()
+    }
+
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap.set_config(config);
+    }
+
+    /// Counts of the objects on the heap and the memory they use.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    /// Frees every object that is no longer reachable from the globals,
+    /// the stack or a pinned value.
+    ///
+    /// The VM also collects on its own while a script runs, whenever the
+    /// heap has grown past the threshold set by [`GcConfig`].
+    ///
+    /// Unreachable instances whose class defines `deinit()` have it called
+    /// once, after the collection, and are freed by a later one unless the
+    /// method stored them somewhere reachable. Weak references to such an
+    /// instance are cleared before `deinit` runs. Instances that are never
+    /// found unreachable have `deinit` called when the VM is dropped.
+    pub fn collect_garbage(&mut self) {
+        self.collect();
+        if let Err(error) = self.run_finalizers() {
+            self.report_finalizer_error(&error);
+        }
+    }
+
+    /// Sets a callback for errors raised by `deinit` methods.
+    ///
+    /// A failing finalizer does not affect the code that was running when
+    /// it was called; the error is passed here, or dropped if no handler
+    /// is set. Running out of instructions or time, or being interrupted,
+    /// is the exception: that stops the script as usual.
+    pub fn set_finalizer_error_handler<F: FnMut(&RuntimeError) + 'static>(&mut self, handler: F) {
+        self.finalizer_error_handler = Some(Box::new(handler));
+    }
+
+    fn collect(&mut self) {
+        let mut tracer = Tracer::new();
+        for slot in &self.stack {
+            tracer.mark_value(&slot.unpack());
+        }
+        for upvalue in &self.open_upvalues {
+            tracer.mark(*upvalue);
+        }
+        for frame in &self.frames {
+            tracer.mark(frame.closure);
+        }
+        for (name, value) in &self.globals {
+            tracer.mark(*name);
+            tracer.mark_value(value);
+        }
+        for value in &self.pinned {
+            tracer.mark_value(value);
+        }
+        for instance in &self.finalizers {
+            tracer.mark(*instance);
+        }
+        tracer.mark(self.init_string);
+        tracer.mark(self.deinit_string);
+        let doomed = self.heap.collect(tracer);
+        self.finalizers.splice(0..0, doomed.into_iter().rev());
+    }
+
+    fn collect_while_running(&mut self) -> Result<(), InterpretResult> {
+        self.collect();
+        match self.run_finalizers() {
+            Ok(()) => Ok(()),
+            Err(error) => self.raise(error),
+        }
+    }
+
+    /// Calls `deinit` on each instance the collector found dead, oldest
+    /// first. Errors that must stop the running script are returned; the
+    /// rest go to the finalizer error handler.
+    fn run_finalizers(&mut self) -> Result<(), RuntimeError> {
+        // A collection during a finalizer only queues more work for the
+        // loop below.
+        if self.finalizing {
+            return Ok(());
+        }
+        self.finalizing = true;
+        let mut result = Ok(());
+        while let Some(instance) = self.finalizers.pop() {
+            let stack_base = self.stack.len();
+            self.push(Value::Instance(instance));
+            let deinit = self.deinit_string;
+            let klass = instance.get().get_class();
+            match self.enter(stack_base, |vm| vm.invoke_from_class(klass, deinit, 0)) {
+                Ok(_) => {}
+                Err(error)
+                    if matches!(
+                        error.kind,
+                        RuntimeErrorKind::InstructionLimit
+                            | RuntimeErrorKind::Timeout
+                            | RuntimeErrorKind::Interrupted
+                    ) =>
+                {
+                    result = Err(error);
+                    break;
+                }
+                Err(error) => self.report_finalizer_error(&error),
+            }
+        }
+        self.finalizing = false;
         result
     }
 
+    fn report_finalizer_error(&mut self, error: &RuntimeError) {
+        if let Some(handler) = &mut self.finalizer_error_handler {
+            // Keep the report in order with what the script printed.
+            let _ = self.output.flush();
+            handler(error);
+        }
+    }
+
+    /// Keeps `value` alive until a matching [`VM::unpin`], for hosts that
+    /// hold on to objects between calls into the VM.
+    ///
+    /// # Panics
+    ///
+    /// If `value` is not [alive](VM::is_alive) in this VM.
+    pub fn pin(&mut self, value: &Value) {
+        assert!(self.is_alive(value), "pin: {value:?} is not alive in this VM");
+        self.pinned.push(*value);
+    }
+
+    /// Undoes one earlier [`VM::pin`] of `value`.
+    pub fn unpin(&mut self, value: &Value) {
+        if let Some(index) = self.pinned.iter().rposition(|pinned| pinned == value) {
+            self.pinned.swap_remove(index);
+        }
+    }
+
+    fn check_limits(&mut self) -> Result<(), InterpretResult> {
+        if self.interrupt.is_interrupted() {
+            return self.runtime_error(RuntimeErrorKind::Interrupted, "Interrupted.");
+        }
+        self.executed += 1;
+        if let Some(max) = self.limits.max_instructions
+            && self.executed > max
+        {
+            return self.runtime_error(
+                RuntimeErrorKind::InstructionLimit,
+                format!("Instruction limit of {max} exceeded."),
+            );
+        }
+        if let Some(max) = self.limits.max_stack
+            && self.stack.len() > max
+        {
+            return self.runtime_error(
+                RuntimeErrorKind::StackLimit,
+                format!("Value stack limit of {max} slots exceeded."),
+            );
+        }
+        if self.executed.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && let Some(deadline) = self.deadline
+            && Instant::now() >= deadline
+        {
+            return self.runtime_error(RuntimeErrorKind::Timeout, "Execution timed out.");
+        }
+        Ok(())
+    }
+
     fn ip(&self) -> usize {
         *self.current_frame().ip.borrow()
     }
@@ -72,37 +515,54 @@ impl VM {
         self.frames.last().unwrap()
     }
 
-    fn get_upvalue(&self, offset: usize) -> Rc<RefCell<Value>> {  
-        let closure = &self.current_frame().closure;
-        closure.get_upvalue(offset)        
+    fn get_upvalue(&self, offset: usize) -> Gc<Upvalue> {
+        self.current_frame().closure.get().get_upvalue(offset)
     }
 
-    fn set_upvalue(&self, offset: usize, value: &Rc<RefCell<Value>>) {
-        let closure = &self.current_frame().closure;
-        closure.modify(offset, value);       
+    /// Returns the open upvalue for stack slot `slot`, creating it if no
+    /// closure has captured that slot yet, so closures share variables.
+    fn capture_upvalue(&mut self, slot: usize) -> Gc<Upvalue> {
+        let index = self
+            .open_upvalues
+            .partition_point(|upvalue| upvalue.get().slot().unwrap() < slot);
+        if let Some(&upvalue) = self.open_upvalues.get(index)
+            && upvalue.get().slot() == Some(slot)
+        {
+            return upvalue;
+        }
+        let upvalue = self.heap.alloc(Upvalue::new(slot));
+        self.open_upvalues.insert(index, upvalue);
+        upvalue
     }
 
-    fn capture_upvalue(&self, offset: usize) -> Rc<RefCell<Value>> {
-        Rc::clone(&self.stack[offset])
+    /// Closes every open upvalue at or above stack slot `from`, before
+    /// those slots are popped.
+    fn close_upvalues(&mut self, from: usize) {
+        let index = self
+            .open_upvalues
+            .partition_point(|upvalue| upvalue.get().slot().unwrap() < from);
+        for upvalue in self.open_upvalues.drain(index..) {
+            upvalue.get().close(&self.stack);
+        }
     }
 
-    fn define_method(&mut self, name:&String) {
-        let method  = self.peek(0).borrow().clone();
+    fn define_method(&mut self, name: Gc<Str>) {
+        let method  = self.peek(0);
 
         let  klass = if let Value::Class(c) = self. 
-        peek(1).borrow().clone() {
+        peek(1) {
             c
         } else {
             panic!("unable to get method's class");
         };
-        if name == "init" {
+        if name == self.init_string {
             if let Value::Closure(closure) = method {
-                klass.set_init_method(closure);
+                klass.get().set_init_method(closure);
             } else {
                 panic!("method should have be closure");
             }
         } else {
-            klass.add_method(name, &method);
+            klass.get().add_method(name, &method);
         }
         
         self.pop();
@@ -111,7 +571,7 @@ impl VM {
 
     fn chunk(&self) -> Rc<Chunk> {
         let closure = &self.current_frame().closure;
-        closure.get_chunk()
+        closure.get().get_chunk()
         // match self.stack[position].borrow().deref() {
         // Value::Closure(c) => c.get_chunk(),
         // Value::Bound(b) => b.get_closure().get_chunk(),
@@ -121,159 +581,262 @@ impl VM {
 
     fn run(&mut self) -> Result<(), InterpretResult> {
         loop {
-            #[cfg(any(feature = "debug_trace_execution", feature = "debug_print_code"))]
+            #[cfg(feature = "debug_trace_execution")]
             {
-                print!("          ");
+                let _ = write!(self.trace, "          ");
                 for slot in &self.stack {
-                    print!("[ {:?} ] ", slot.borrow().to_string());
+                    let _ = write!(self.trace, "[ {:?} ] ", slot.unpack().show().to_string());
                 }
-                println!();
+                let _ = writeln!(self.trace);
 
-                self.chunk().disassemble_instruction(self.ip());
+                let ip = self.ip();
+                let _ = self.chunk().disassemble_instruction(&mut self.trace, ip);
             }
 
             let instruction = self.read_byte().into();
+            if self.heap.should_collect() {
+                self.collect_while_running()?;
+                self.check_memory(0)?;
+            }
+            self.check_limits()?;
             match instruction {
                 OpCode::Print => {
-                    println!("{}", self.pop().borrow());
+                    let text = self.pop().show().to_string();
+                    if let Err(e) = self.output.print(&text) {
+                        return self.runtime_error(RuntimeErrorKind::Io, e.to_string());
+                    }
                 }
                 OpCode::SuperInoke => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     let arg_count = self.read_byte() as usize;
                     let method_name = if let Value::Str(s) = constant {
                         s
                     } else {
                         panic!("No superclass method");
                     };
-                    let superclass_value = self.pop().borrow().clone();
+                    let superclass_value = self.pop();
                     if let Value::Class(superclass) = superclass_value {
-                        if !self.invoke_from_class(superclass, &method_name, arg_count) {
-                            return Err(InterpretResult::RuntimeError)
-                        }
+                        self.invoke_from_class(superclass, method_name, arg_count)?;
                     }
                 }
                 OpCode::GetSuper => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     let method_name = if let Value::Str(s) = constant {
                         s
                     } else {
                         panic!("No superclass method");
                     };
-                    let superclass_value = self.pop().borrow().clone();
+                    let superclass_value = self.pop();
                     if let Value::Class(superclass) = superclass_value {
-                        if !self.bind_method(superclass, &method_name) {
-                            return Err(InterpretResult::RuntimeError)
-                        }
+                        self.bind_method(superclass, method_name)?;
                     }
 
                 }
+                OpCode::BuildList => {
+                    let count = self.read_byte() as usize;
+                    if let Err(error) = self.reserve(Heap::object_size::<List>(count * List::ITEM_SIZE)) {
+                        return self.raise(error);
+                    }
+                    let start = self.stack.len() - count;
+                    let items = self.stack[start..].iter().map(|item| item.unpack()).collect();
+                    let list = self.heap.alloc(List::new(items));
+                    self.stack.truncate(start);
+                    self.push(Value::List(list));
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_byte() as usize;
+                    if let Err(error) = self.reserve(Heap::object_size::<Map>(count * Map::ENTRY_SIZE)) {
+                        return self.raise(error);
+                    }
+                    let start = self.stack.len() - count * 2;
+                    let map = Map::new();
+                    for pair in self.stack[start..].chunks(2) {
+                        if let Err(error) = map.insert(pair[0].unpack(), pair[1].unpack()) {
+                            return self.raise(error);
+                        }
+                    }
+                    let map = self.heap.alloc(map);
+                    self.stack.truncate(start);
+                    self.push(Value::Map(map));
+                }
+                OpCode::GetIndex => {
+                    let item = match self.peek(1) {
+                        Value::List(list) => list.get().index(self.peek(0)).map(|index| list.get().get(index).unwrap()),
+                        Value::Map(map) => map.get().lookup(self.peek(0)),
+                        _ => return self.runtime_error(RuntimeErrorKind::Type, "Only lists and maps can be indexed."),
+                    };
+                    let item = match item {
+                        Ok(item) => item,
+                        Err(error) => return self.raise(error),
+                    };
+                    self.stack.truncate(self.stack.len() - 2);
+                    self.push(item);
+                }
+                OpCode::SetIndex => {
+                    let value = self.peek(0);
+                    if let Value::Map(map) = self.peek(2)
+                        && !map.get().has(&self.peek(1))
+                        && let Err(error) = self.reserve(Map::ENTRY_SIZE)
+                    {
+                        return self.raise(error);
+                    }
+                    let stored = match self.peek(2) {
+                        Value::List(list) => list.get().index(self.peek(1)).map(|index| {
+                            list.get().set(index, value);
+                        }),
+                        Value::Map(map) => map.get().insert(self.peek(1), value).map(|added| {
+                            if added {
+                                self.heap.grow(map, Map::ENTRY_SIZE);
+                            }
+                        }),
+                        _ => return self.runtime_error(RuntimeErrorKind::Type, "Only lists and maps can be indexed."),
+                    };
+                    if let Err(error) = stored {
+                        return self.raise(error);
+                    }
+                    self.stack.truncate(self.stack.len() - 3);
+                    self.push(value);
+                }
+                OpCode::GetSlice => {
+                    let Value::List(list) = self.peek(2) else {
+                        return self.runtime_error(RuntimeErrorKind::Type, "Only lists can be sliced.");
+                    };
+                    let items = match list.get().slice(self.peek(1), self.peek(0)) {
+                        Ok(items) => items,
+                        Err(error) => return self.raise(error),
+                    };
+                    if let Err(error) = self.reserve(Heap::object_size::<List>(items.len() * List::ITEM_SIZE)) {
+                        return self.raise(error);
+                    }
+                    let slice = self.heap.alloc(List::new(items));
+                    self.stack.truncate(self.stack.len() - 3);
+                    self.push(Value::List(slice));
+                }
                 OpCode::Inherit => {
-                    let value = self.peek(1).borrow().clone();
+                    let value = self.peek(1);
                     let superclass = if let Value::Class(c) =  value {
                         c
                     } else {
-                       return self.runtime_error("Superclass must be a class.");                 
+                       return self.runtime_error(RuntimeErrorKind::Type, "Superclass must be a class.");                 
                     };
                   
-                    let subclass = if let Value::Class(c) =  self.peek(0).borrow().clone() {
+                    let subclass = if let Value::Class(c) =  self.peek(0) {
                         c
                     } else {
                         panic!("No sub class found on stack")
                     };
 
-                    subclass.copy_method(&superclass);
+                    subclass.get().copy_method(superclass.get());
 
                     self.pop();
                 }
                 OpCode::Invoke => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     let method_name = if let Value::Str(s) = constant {
                         s
                     } else {
                         panic!("Unable to get class methods");
                     };
                     let arg_count = self.read_byte() as usize;
-                    if !self.invoke(method_name, arg_count) {
-                        return Err(InterpretResult::RuntimeError)
-                    }
+                    self.invoke(method_name, arg_count)?;
 
                 }
                 OpCode::Method => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     let method_name = if let Value::Str(s) = constant {
                         s
                     } else {
                         panic!("Unable to get class meethods");
                     };
-                    self.define_method(&method_name)
+                    self.define_method(method_name)
                 }
                 OpCode::SetProperty => {
                     let  instance = if let Value::Instance(i) = self
-                    .peek(1).borrow().clone(){    
+                    .peek(1){    
                         i
                     }  else {
-                        return self.runtime_error("Only Instaces have fields.")
+                        return self.runtime_error(RuntimeErrorKind::Type, "Only Instaces have fields.")
                     };  
                                            
-                    let constant  = self.read_constant().clone();                         
+                    let constant  = self.read_constant();                         
                    let field_name = if let Value::Str(s) = constant {
                         s
                     } else {
                         panic!("Unable to get class field from tables");
                     };
 
+                    let added = instance.get().get_field(field_name).is_none();
+                    if added && let Err(error) = self.reserve(Instance::FIELD_SIZE) {
+                        return self.raise(error);
+                    }
                     let value = self.pop();
-                    instance.set_field(&field_name, &value.borrow().clone());  
+                    if added {
+                        self.heap.grow(instance, Instance::FIELD_SIZE);
+                    }
+                    instance.get().set_field(field_name, &value);  
 
                     self.pop();                  
-                    self.push(value.borrow().clone());
+                    self.push(value);
                 }
                 OpCode::GetProperty => {                    
-                    let instance = if let Value::Instance(i) = self
-                    .peek(0).borrow().clone(){    
+                    let receiver = self.peek(0);
+                    if let Value::Foreign(_) | Value::WeakMap(_) | Value::List(_) | Value::Map(_) = receiver {
+                        let constant = self.read_constant();
+                        let method_name = if let Value::Str(s) = constant {
+                            s
+                        } else {
+                            panic!("Unable to get method name from table");
+                        };
+                        let method = self.bind_native_method(receiver, method_name.get().as_str())?;
+                        self.pop();
+                        self.push(Value::Native(method));
+                        continue;
+                    }
+                    let instance = if let Value::Instance(i) = receiver {    
                         i
                     }  else {
-                        return self.runtime_error("Only Instaces have properties.")
+                        return self.runtime_error(RuntimeErrorKind::Type, "Only Instaces have properties.")
                     };  
-                    let constant  = self.read_constant().clone();                         
+                    let constant  = self.read_constant();                         
                     let field_name = if let Value::Str(s) = constant {
                             s
                     } else {
                         panic!("Unable to get class name from table");
                     };
                     
-                    if let Some(value) = instance.get_field(&field_name) {
+                    if let Some(value) = instance.get().get_field(field_name) {
                         self.pop();
-                        self.push(value.clone());
-                    } else if !self.bind_method(instance.get_class(), &field_name) {                    
-                        return self.runtime_error(format!("Undefined property '{field_name}'"));
+                        self.push(value);
+                    } else {
+                        self.bind_method(instance.get().get_class(), field_name)?;
                     }                      
                                
                 }                
                 OpCode::Class => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     let class_string = if let Value::Str(s) = constant {
                         s
                     } else {
                         panic!("Unable to get class name from tables");
                     };
-                    self.push(Value::Class(Rc::new(Class::new(class_string))));
+                    let class = self.heap.alloc(Class::new(class_string));
+                    self.push(Value::Class(class));
                 }
                 OpCode::GetUpvalue => {
                     let slot = self.read_byte() as usize;
-                    self.push(self.get_upvalue(slot).borrow().deref().clone())
+                    let value = self.get_upvalue(slot).get().get(&self.stack);
+                    self.stack.push(value);
                 }
                 OpCode::SetUpvalue => {
                     let slot = self.read_byte() as usize;
-                    let value = self.peek(0);
-                    self.set_upvalue(slot, value);
+                    let value = *self.stack.last().unwrap();
+                    self.get_upvalue(slot).get().set(&mut self.stack, value);
                 }
                 OpCode::Closure => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     if let Value::Func(function) = constant {
-                        let upvalue_count = function.upvalue();
-                        let closure = Closure::new(function);
-
+                        let upvalue_count = function.get().upvalue();
+                        let mut upvalues = Vec::with_capacity(upvalue_count);
                         for _ in 0..upvalue_count {
                             let is_local = self.read_byte() != 0;
                             let index = self.read_byte() as usize;
@@ -283,19 +846,17 @@ impl VM {
                             } else {
                                 self.get_upvalue(index)
                             };
-                            closure.push_upvalue(&captured);
+                            upvalues.push(captured);
                         }
-                        self.stack
-                            .push(Rc::new(RefCell::new(Value::Closure(Rc::new(closure)))));
+                        let closure = self.heap.alloc(Closure::new(function, upvalues));
+                        self.push(Value::Closure(closure));
                     } else {
-                        panic!("Tried to read fucntion from constant table but got {constant:}");
+                        panic!("Tried to read fucntion from constant table but got {constant:?}");
                     };
                 }
                 OpCode::Call => {
                     let arg_count = self.read_byte() as usize;
-                    if !self.call_value(arg_count) {
-                        return Err(InterpretResult::RuntimeError);
-                    }
+                    self.call_value(arg_count)?;
                 }
                 OpCode::Loop => {
                     let offset = self.read_short();
@@ -307,92 +868,138 @@ impl VM {
                 }
                 OpCode::JumpIfFalse => {
                     let offset = self.read_short();
-                    if self.peek(0).borrow().is_falsey() {
+                    if self.peek(0).is_falsey() {
                         self.current_frame().inc(offset);
                     }
                 }
                 OpCode::Return => {
-                    let result = self.pop().borrow().deref().clone();
-                    let prev_frame = self.frames.pop();
-                    if self.frames.is_empty() {
-                        self.pop();
+                    let result = self.pop();
+                    let slots = self.frames.pop().unwrap().slots;
+                    self.close_upvalues(slots);
+                    self.stack.truncate(slots);
+                    self.push(result);
+                    if self.frames.len() == self.frame_base {
                         return Ok(());
                     }
-                    self.stack.truncate(prev_frame.unwrap().slots);
-                    self.push(result);
                 }
                 OpCode::Constant => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     self.push(constant);
                 }
                 OpCode::Nil => self.push(Value::Nil),
                 OpCode::True => self.push(Value::Boolean(true)),
                 OpCode::False => self.push(Value::Boolean(false)),
-                OpCode::Pop | OpCode::CloseUpvalue => {
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
                     self.pop();
                 }
                 OpCode::Negate => {
                     //if let Value::Number(_) = self.peek(0)
-                    if self.peek(0).borrow().is_number() {
-                        let value = self.pop().borrow().deref().clone();
+                    if self.peek(0).is_number() {
+                        let value = self.pop();
                         self.push(-value);
                     } else {
-                        return self.runtime_error("Operand must be a number");
+                        return self.runtime_error(RuntimeErrorKind::Type, "Operand must be a number");
                     }
                 }
                 OpCode::DefineGlobal => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     if let Value::Str(name) = constant {
-                        let value = self.pop().borrow().deref().clone();
-                        self.globals.insert(name.clone(), value.clone());
+                        let value = self.pop();
+                        self.globals.insert(name, value);
                     } else {
                         panic!("DefineGlobal: constant is not a string");
                     }
                 }
                 OpCode::GetGlobal => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     if let Value::Str(name) = constant {
                         if let Some(value) = self.globals.get(&name) {
-                            self.push(value.clone());
+                            self.push(*value);
                         } else {
-                            return self.runtime_error(format!("Undefined variable '{:}'", name));
+                            return self.runtime_error(
+                                RuntimeErrorKind::UndefinedVariable,
+                                format!("Undefined variable '{:}'", name.get()),
+                            );
                         }
                     }
                 }
                 OpCode::SetGlobal => {
-                    let constant = self.read_constant().clone();
+                    let constant = self.read_constant();
                     if let Value::Str(name) = constant {
-                        let p = self.peek(0).borrow().deref().clone();
-                        if let Entry::Occupied(mut o) = self.globals.entry(name.clone()) {
+                        let p = self.peek(0);
+                        if let Entry::Occupied(mut o) = self.globals.entry(name) {
                             *o.get_mut() = p;
                         } else {
-                            return self.runtime_error(format!("Undefined variable '{:}'", name));
+                            return self.runtime_error(
+                                RuntimeErrorKind::UndefinedVariable,
+                                format!("Undefined variable '{:}'", name.get()),
+                            );
                         }
                     }
                 }
                 OpCode::GetLocal => {
                     let slot = self.read_byte() as usize;
                     let slot_offest = self.current_frame().slots;
-                    self.stack.push(self.stack[slot_offest + slot].clone());
+                    self.stack.push(self.stack[slot_offest + slot]);
                 }
                 OpCode::SetLocal => {
                     let slot = self.read_byte() as usize;
                     let slot_offset = self.current_frame().slots;
-                    self.stack[slot_offset + slot] = self.peek(0).clone();
+                    self.stack[slot_offset + slot] = *self.stack.last().unwrap();
                 }
                 OpCode::Equal => {
                     let b = self.pop();
                     let a = self.pop();
                     self.push(Value::Boolean(a == b))
                 }
-                OpCode::Greater => self.binary_op(|a, b| Value::Boolean(a > b))?,
-                OpCode::Less => self.binary_op(|a, b| Value::Boolean(a < b))?,
-                OpCode::Add => self.binary_op(|a, b| a + b)?,
+                OpCode::Greater => self.binary_op(|a, b| Value::Boolean(a.compare(&b) == Some(Ordering::Greater)))?,
+                OpCode::Less => self.binary_op(|a, b| Value::Boolean(a.compare(&b) == Some(Ordering::Less)))?,
+                OpCode::Add => {
+                    if self.peek(0).is_string() && self.peek(1).is_string() {
+                        self.concatenate()?;
+                    } else {
+                        self.binary_op(|a, b| a + b)?;
+                    }
+                }
                 OpCode::Subtract => self.binary_op(|a, b| a - b)?,
                 OpCode::Multiply => self.binary_op(|a, b| a * b)?,
                 OpCode::Divide => self.binary_op(|a, b| a / b)?,
+                // Both round toward negative infinity, so that
+                // `a == (a ~/ b) * b + a % b`.
+                OpCode::Modulo => self.number_op(|a, b| {
+                    let remainder = a % b;
+                    if remainder != 0.0 && (remainder < 0.0) != (b < 0.0) {
+                        remainder + b
+                    } else {
+                        remainder
+                    }
+                })?,
+                OpCode::IntDivide => self.number_op(|a, b| (a / b).floor())?,
+                OpCode::Power => self.number_op(f64::powf)?,
+                OpCode::BitAnd => self.integer_op(|a, b| Ok(a & b))?,
+                OpCode::BitOr => self.integer_op(|a, b| Ok(a | b))?,
+                OpCode::BitXor => self.integer_op(|a, b| Ok(a ^ b))?,
+                OpCode::ShiftLeft => self.integer_op(|a, b| {
+                    u32::try_from(b).ok().and_then(|b| a.checked_shl(b)).ok_or(SHIFT_ERROR)
+                })?,
+                OpCode::ShiftRight => self.integer_op(|a, b| {
+                    u32::try_from(b).ok().and_then(|b| a.checked_shr(b)).ok_or(SHIFT_ERROR)
+                })?,
+                OpCode::BitNot => match as_integer(self.peek(0)) {
+                    Some(value) => {
+                        self.pop();
+                        self.push(Value::Number(!value as f64));
+                    }
+                    None => {
+                        return self.runtime_error(RuntimeErrorKind::Type, "Operand must be an integer.");
+                    }
+                },
                 OpCode::Not => {
-                    let value = self.pop().borrow().deref().clone();
+                    let value = self.pop();
                     self.push(Value::Boolean(value.is_falsey()))
                 }
             }
@@ -400,125 +1007,255 @@ impl VM {
     }
 
     fn push(&mut self, value: Value) {
-        self.stack.push(Rc::new(RefCell::new(value)))
+        self.stack.push(value.into())
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap().unpack()
     }
 
-    fn pop(&mut self) -> Rc<RefCell<Value>> {
-        self.stack.pop().unwrap()
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - distance - 1].unpack()
     }
 
-    fn peek(&self, distance: usize) -> &Rc<RefCell<Value>> {
-        &self.stack[self.stack.len() - distance - 1]
+    /// Replaces the value `distance` slots below the top of the stack.
+    fn replace(&mut self, distance: usize, value: Value) {
+        let index = self.stack.len() - distance - 1;
+        self.stack[index] = value.into();
     }
 
-    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> bool {
-        let arity = closure.arity();      
+    fn call(&mut self, closure: Gc<Closure>, arg_count: usize) -> Result<(), InterpretResult> {
+        let arity = closure.get().arity();      
         if arity != arg_count {
-            let _ = self.runtime_error(format!("Expected {arity} arguments but got {arg_count}"));
-            return false;
+            return self.runtime_error(
+                RuntimeErrorKind::Arity,
+                format!("Expected {arity} arguments but got {arg_count}"),
+            );
         }
 
-        if self.frames.len() == 256 {
-            let _ = self.runtime_error("Stack overflow");
+        if self.frames.len() >= self.limits.max_frames {
+            return self.runtime_error(RuntimeErrorKind::StackOverflow, "Stack overflow");
         }
 
         self.frames.push(CallFrame {
-            closure: Rc::clone(&closure),
+            closure,
             ip: RefCell::new(0),
             slots: self.stack.len() - arg_count - 1,
         });
 
-        true
+        Ok(())
     }
 
-    fn call_value(&mut self, arg_count: usize) -> bool {
-        let callee = self.peek(arg_count).borrow().deref().clone();
-        let success = match callee {
+    fn call_value(&mut self, arg_count: usize) -> Result<(), InterpretResult> {
+        let callee = self.peek(arg_count);
+        match callee {
             Value::Class(klass) => {
-                let stack_top = self.stack.len();
-                let init = klass.get_init_method();
-                self.stack[stack_top - arg_count -1] = Rc::new(
-                    RefCell::new(Value::Instance(Rc::new(Instance::new(klass)
-                ))));
+                let init = klass.get().get_init_method();
+                let instance = self.heap.alloc(Instance::new(klass));
+                if !self.shutting_down && klass.get().get_mehtod(self.deinit_string).is_some() {
+                    self.heap.register_finalizer(instance);
+                }
+                self.replace(arg_count, Value::Instance(instance));
                 if let Some(initializer) = init {
                     self.call(initializer, arg_count)
                 } else if arg_count != 0 {
-                    let _ = self.runtime_error(format!("Expect 0 arguments but got {arg_count}"));
-                    false
+                    self.runtime_error(
+                        RuntimeErrorKind::Arity,
+                        format!("Expect 0 arguments but got {arg_count}"),
+                    )
                 } else {
-                     true
+                     Ok(())
                 }
                 
             }
-            Value::Closure(closure) => {
-                return self.call(closure, arg_count);
-            }
-            Value::Native(f) => {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::Native(native) => {
+                let f = Rc::clone(native.get().function());
+                let arity = f.arity();
+                if !arity.accepts(arg_count) {
+                    return self.runtime_error(
+                        RuntimeErrorKind::Arity,
+                        format!("Expected {arity} arguments but got {arg_count}"),
+                    );
+                }
                 let stack_top = self.stack.len();
-                let result = f.call(arg_count, &self.stack[stack_top - arg_count..stack_top]);
-                self.stack.truncate(stack_top - (arg_count + 1));
-                self.push(result);
-                true
+                let values: Vec<Value> = self.stack[stack_top - arg_count..stack_top]
+                    .iter()
+                    .map(|value| value.unpack())
+                    .collect();
+                let result = f
+                    .call(self, Args::new(f.name(), &values))
+                    .and_then(|result| self.check_handles([&result]).map(|()| result));
+                self.return_from_native(result, arg_count, || f.name().to_string())
             }
             Value::Bound(method) => {
                   
-                let stack_top = self.stack.len();
-                self.stack[stack_top - arg_count -1] = Rc::new(
-                    RefCell::new(method.get_recevier())
-                );
+                self.replace(arg_count, method.get().get_recevier());
                 //let closure = method.get_closure(); 
-                return self.call( method.get_closure(), arg_count);
+                self.call( method.get().get_closure(), arg_count)
             }
-            _ => false,
-        };
-
-        if !success {
-            let _ = self.runtime_error("Can only call functions and classes.");
+            _ => self.runtime_error(
+                RuntimeErrorKind::NotCallable,
+                "Can only call functions and classes.",
+            ),
         }
-        success
     }
 
-    fn invoke_from_class(&mut self, klass:Rc<Class>, name: &str, arg_count: usize) -> bool {
-        if let Some(closure) = klass.get_mehtod(name) {
+    fn invoke_from_class(
+        &mut self,
+        klass: Gc<Class>,
+        name: Gc<Str>,
+        arg_count: usize,
+    ) -> Result<(), InterpretResult> {
+        if let Some(closure) = klass.get().get_mehtod(name) {
             self.call(closure, arg_count)
         } else {
-            let _ = self.runtime_error(format!("Undefined property '{name}'."));
-            false
+            self.runtime_error(
+                RuntimeErrorKind::UndefinedProperty,
+                format!("Undefined property '{}'.", name.get()),
+            )
         }
     }
 
-    fn invoke(&mut self, name:String, arg_count: usize) -> bool {
-        let receiver = self.peek(arg_count).borrow().clone();
+    fn invoke(&mut self, name: Gc<Str>, arg_count: usize) -> Result<(), InterpretResult> {
+        let receiver = self.peek(arg_count);
 
         if let Value::Instance(instance) = receiver {
-            if let Some(value) = instance.get_field(&name) {
-                let stack_top = self.stack.len();
-                self.stack[stack_top - arg_count - 1] = Rc::new(
-                    RefCell::new(value)); 
+            if let Some(value) = instance.get().get_field(name) {
+                self.replace(arg_count, value);
                 self.call_value(arg_count)
 
             } else {
-            self.invoke_from_class(instance.get_class(), &name, arg_count)
+            self.invoke_from_class(instance.get().get_class(), name, arg_count)
             }
+        } else if let Value::List(_) = receiver {
+            self.invoke_builtin("List", List::method(name.get().as_str()), name, arg_count)
+        } else if let Value::Map(_) = receiver {
+            self.invoke_builtin("Map", Map::method(name.get().as_str()), name, arg_count)
+        } else if let Value::WeakMap(_) = receiver {
+            self.invoke_builtin("WeakMap", WeakMap::method(name.get().as_str()), name, arg_count)
+        } else if let Value::Foreign(_) = receiver {
+            let method = self.bind_native_method(receiver, name.get().as_str())?;
+            self.replace(arg_count, Value::Native(method));
+            self.call_value(arg_count)
         } else {
-            let _ = self.runtime_error("Only instances have methods.");
-            false
+            self.runtime_error(RuntimeErrorKind::Type, "Only instances have methods.")
         }
     }
 
-    fn bind_method(&mut self, klass:Rc<Class>, name: &String) -> bool {
-        if let Some(method) = klass.get_mehtod(name) {
-            let value = self.peek(0).borrow().clone();
+    /// Calls built-in `method` on the receiver below its arguments, without
+    /// binding it to a `Native` first.
+    fn invoke_builtin(
+        &mut self,
+        type_name: &str,
+        method: Option<(Arity, BuiltinMethodBody)>,
+        name: Gc<Str>,
+        arg_count: usize,
+    ) -> Result<(), InterpretResult> {
+        let Some((arity, body)) = method else {
+            return self.runtime_error(
+                RuntimeErrorKind::UndefinedProperty,
+                format!("Undefined property '{}'.", name.get()),
+            );
+        };
+        if !arity.accepts(arg_count) {
+            return self.runtime_error(
+                RuntimeErrorKind::Arity,
+                format!("Expected {arity} arguments but got {arg_count}"),
+            );
+        }
+        let receiver = self.peek(arg_count);
+        let stack_top = self.stack.len();
+        let values: Vec<Value> = self.stack[stack_top - arg_count..stack_top]
+            .iter()
+            .map(|value| value.unpack())
+            .collect();
+        let result = body(self, receiver, Args::method(type_name, name.get().as_str(), &values));
+        self.return_from_native(result, arg_count, || format!("{type_name}.{}", name.get()))
+    }
+
+    /// Replaces a native's callee and arguments with what it returned, or
+    /// raises its error with the native on top of the trace.
+    fn return_from_native(
+        &mut self,
+        result: Result<Value, RuntimeError>,
+        arg_count: usize,
+        name: impl FnOnce() -> String,
+    ) -> Result<(), InterpretResult> {
+        match result {
+            Ok(result) => {
+                let stack_top = self.stack.len();
+                self.stack.truncate(stack_top - (arg_count + 1));
+                self.push(result);
+                Ok(())
+            }
+            Err(mut error) => {
+                // Frames of any Lox code the native called back
+                // into are already on the trace.
+                let line = self
+                    .frames
+                    .last()
+                    .map_or(0, |frame| self.stack_frame(frame).line);
+                error.trace.push(StackFrame {
+                    function: name(),
+                    line,
+                    offset: 0,
+                    span: Span::default(),
+                    source: None,
+                });
+                self.raise(error)
+            }
+        }
+    }
+
+    /// Binds method `name` of a foreign or built-in object to `receiver`.
+    fn bind_native_method(
+        &mut self,
+        receiver: Value,
+        name: &str,
+    ) -> Result<Gc<Native>, InterpretResult> {
+        let method = match receiver {
+            Value::Foreign(foreign) => Foreign::bind(foreign, name),
+            Value::WeakMap(_) => WeakMap::method(name)
+                .map(|method| BuiltinMethod::bind("WeakMap", name, receiver, method)),
+            Value::List(_) => {
+                List::method(name).map(|method| BuiltinMethod::bind("List", name, receiver, method))
+            }
+            Value::Map(_) => {
+                Map::method(name).map(|method| BuiltinMethod::bind("Map", name, receiver, method))
+            }
+            _ => None,
+        };
+        match method {
+            Some(method) => {
+                if let Err(error) = self.reserve(Heap::object_size::<Native>(method.payload_size())) {
+                    return self.raise(error);
+                }
+                Ok(self.heap.alloc(method))
+            }
+            None => self.runtime_error(
+                RuntimeErrorKind::UndefinedProperty,
+                format!("Undefined property '{}'.", name),
+            ),
+        }
+    }
+
+    fn bind_method(&mut self, klass: Gc<Class>, name: Gc<Str>) -> Result<(), InterpretResult> {
+        if let Some(method) = klass.get().get_mehtod(name) {
+            let value = self.peek(0);
             let bound = BoundMethod::new(
                 &value,
                 &method
             );
             self.pop();
-            self.push(Value::Bound(Rc::new(bound)));            
-            true
+            let bound = self.heap.alloc(bound);
+            self.push(Value::Bound(bound));            
+            Ok(())
         } else {
-             let _ = self.runtime_error(format!("Undefied property '{}'", name));
-            false
+            self.runtime_error(
+                RuntimeErrorKind::UndefinedProperty,
+                format!("Undefined property '{}'", name.get()),
+            )
         }
         
     }
@@ -538,40 +1275,214 @@ impl VM {
     fn read_constant(&mut self) -> Value {
         let index = self.chunk().read(self.ip()) as usize;
         self.current_frame().inc(1);
-        self.chunk().get_constant(index).clone()
+        self.chunk().get_constant(index)
+    }
+
+    fn concatenate(&mut self) -> Result<(), InterpretResult> {
+        if let (Value::Str(a), Value::Str(b)) = (self.peek(1), self.peek(0)) {
+            // A long enough string could blow far past the limit in one go.
+            let len = a.get().as_str().len() + b.get().as_str().len();
+            if let Err(error) = self.reserve(Heap::object_size::<Str>(len)) {
+                return self.raise(error);
+            }
+            let result = self.heap.intern(&(a.get().as_str().to_owned() + b.get().as_str()));
+            self.stack.truncate(self.stack.len() - 2);
+            self.push(Value::Str(result));
+        }
+        Ok(())
+    }
+
+    fn stack_bytes(&self) -> usize {
+        self.stack.capacity() * std::mem::size_of::<PackedValue>()
+    }
+
+    /// Makes room for `bytes` about to be allocated in one go, so that a
+    /// big allocation can't go past the memory limit before the next
+    /// instruction notices. Collects garbage if they would not fit, and
+    /// fails with `OutOfMemory` if they still don't.
+    pub(crate) fn reserve(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        if !self.heap.over_limit(bytes + self.stack_bytes()) {
+            return Ok(());
+        }
+        self.collect();
+        self.run_finalizers()?;
+        match self.limits.max_memory {
+            Some(max) if self.heap.over_limit(bytes + self.stack_bytes()) => Err(RuntimeError::new(
+                RuntimeErrorKind::OutOfMemory,
+                format!("Out of memory: limit of {max} bytes exceeded."),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Raises an out-of-memory error if the heap, the stack and `extra`
+    /// bytes about to be allocated would not fit in the memory limit.
+    fn check_memory(&mut self, extra: usize) -> Result<(), InterpretResult> {
+        if let Some(max) = self.limits.max_memory
+            && self.heap.over_limit(extra + self.stack_bytes())
+        {
+            return self.runtime_error(
+                RuntimeErrorKind::OutOfMemory,
+                format!("Out of memory: limit of {max} bytes exceeded."),
+            );
+        }
+        Ok(())
     }
 
     fn binary_op<F>(&mut self, f: F) -> Result<(), InterpretResult>
     where
         F: Fn(Value, Value) -> Value,
     {
-        if (self.peek(0).borrow().is_string() && self.peek(1).borrow().is_string())
-            || (self.peek(0).borrow().is_number() && self.peek(1).borrow().is_number())
-        {
-            let b = self.pop().borrow().deref().clone();
-            let a = self.pop().borrow().deref().clone();
+        let b = self.peek(0);
+        let a = self.peek(1);
+        if (a.is_string() && b.is_string()) || (a.is_number() && b.is_number()) {
+            self.stack.truncate(self.stack.len() - 2);
             self.push(f(a, b));
             Ok(())
         } else {
-            println!("{:?} and {:?}", self.peek(0), self.peek(1));
-            self.runtime_error("Operands must be two numbers or two strings.")
+            self.runtime_error(
+                RuntimeErrorKind::Type,
+                "Operands must be two numbers or two strings.",
+            )
+        }
+    }
+
+    fn number_op<F>(&mut self, f: F) -> Result<(), InterpretResult>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(a), Value::Number(b)) => {
+                self.stack.truncate(self.stack.len() - 2);
+                self.push(Value::Number(f(a, b)));
+                Ok(())
+            }
+            _ => self.runtime_error(RuntimeErrorKind::Type, "Operands must be numbers."),
+        }
+    }
+
+    fn integer_op<F>(&mut self, f: F) -> Result<(), InterpretResult>
+    where
+        F: Fn(i64, i64) -> Result<i64, &'static str>,
+    {
+        let (Some(a), Some(b)) = (as_integer(self.peek(1)), as_integer(self.peek(0))) else {
+            return self.runtime_error(RuntimeErrorKind::Type, "Operands must be integers.");
+        };
+        match f(a, b) {
+            Ok(result) => {
+                self.stack.truncate(self.stack.len() - 2);
+                self.push(Value::Number(result as f64));
+                Ok(())
+            }
+            Err(message) => self.runtime_error(RuntimeErrorKind::Range, message),
+        }
+    }
+
+    fn runtime_error<R, T: Into<String>>(
+        &mut self,
+        kind: RuntimeErrorKind,
+        err_msg: T,
+    ) -> Result<R, InterpretResult> {
+        self.raise(RuntimeError::new(kind, err_msg))
+    }
+
+    /// Appends the active call frames to `error`'s trace and unwinds them,
+    /// stopping at the frames of any outer run.
+    fn raise<R>(&mut self, mut error: RuntimeError) -> Result<R, InterpretResult> {
+        for frame in self.frames[self.frame_base..].iter().rev() {
+            error.trace.push(self.stack_frame(frame));
         }
+        self.frames.truncate(self.frame_base);
+        self.close_upvalues(self.stack_base);
+        self.stack.truncate(self.stack_base);
+        Err(InterpretResult::RuntimeError(error))
     }
 
-    fn runtime_error<T: Into<String>>(&mut self, err_msg: T) -> Result<(), InterpretResult> {
-        eprintln!("{}", err_msg.into());
-        for frame in self.frames.iter().rev() {                       
-            let instruction = *frame.ip.borrow() - 1_usize;
-            let closure = &frame.closure;
-            let line = closure.get_chunk().get_line(instruction);
-            eprintln!("[line {line} in {}", closure.stack_name());           
+    fn stack_frame(&self, frame: &CallFrame) -> StackFrame {
+        let instruction = *frame.ip.borrow() - 1_usize;
+        let closure = &frame.closure;
+        let chunk = closure.get().get_chunk();
+        StackFrame {
+            function: closure.get().stack_name().to_string(),
+            line: chunk.get_line(instruction),
+            offset: instruction,
+            span: chunk.get_span(instruction),
+            source: Some(chunk.source()),
         }
-        self.reset_stack();
-        Err(InterpretResult::RuntimeError)
     }
 
-    fn define_native<T: Into<String>>(&mut self, name: T, function: &Rc<dyn NativeFunc>) {
+    /// Registers a native function as a global under `name`.
+    pub fn define_native<T: Into<String>>(&mut self, name: T, function: &Rc<dyn NativeFunc>) {
+        let name = self.heap.intern(&name.into());
+        let native = self.heap.alloc(Native::new(Rc::clone(function)));
+        self.globals.insert(name, Value::Native(native));
+    }
+
+    /// Registers a Rust closure as a native function named `name`.
+    pub fn define_native_fn<T, F>(&mut self, name: T, arity: Arity, body: F)
+    where
+        T: Into<String>,
+        F: Fn(&mut VM, Args) -> Result<Value, RuntimeError> + 'static,
+    {
+        let name = name.into();
+        let function: Rc<dyn NativeFunc> = Rc::new(NativeFn::new(name.clone(), arity, body));
+        self.define_native(name, &function);
+    }
+
+    pub(crate) fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Borrows the object behind `handle`, or returns `None` if it has
+    /// been collected or belongs to another VM.
+    pub fn object<T: HeapObject>(&self, handle: Gc<T>) -> Option<&T> {
+        self.heap.get(handle)
+    }
+
+    /// Whether `value` can be passed to this VM: it is a number, boolean
+    /// or `nil`, or an object that is alive in this VM.
+    pub fn is_alive(&self, value: &Value) -> bool {
+        self.heap.contains(value)
+    }
+
+    /// Returns the string with contents `text`, for building
+    /// [`Value::Str`]s to pass into Lox.
+    pub fn intern(&mut self, text: &str) -> Gc<Str> {
+        self.heap.intern(text)
+    }
+
+    /// Wraps `data` as an object of the foreign class `class`.
+    pub fn new_foreign<T: Any>(&mut self, class: &Rc<ForeignClass>, data: T) -> Value {
+        Value::Foreign(self.heap.alloc(Foreign::new(class, data)))
+    }
+
+    /// Returns a copy of the global variable `name`, if it is defined.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        let name = self.heap.lookup(name)?;
+        self.globals.get(&name).cloned()
+    }
+
+    /// Defines or overwrites the global variable `name`.
+    ///
+    /// # Panics
+    ///
+    /// If `value` is not [alive](VM::is_alive) in this VM.
+    pub fn set_global<T: AsRef<str>>(&mut self, name: T, value: Value) {
+        assert!(self.is_alive(&value), "set_global: {value:?} is not alive in this VM");
+        let name = self.heap.intern(name.as_ref());
+        self.globals.insert(name, value);
+    }
+
+    /// Undefines the global variable `name`, returning its last value.
+    pub fn remove_global(&mut self, name: &str) -> Option<Value> {
+        let name = self.heap.lookup(name)?;
+        self.globals.remove(&name)
+    }
+
+    /// Iterates over all globals, natives included, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> {
         self.globals
-            .insert(name.into(), Value::Native(Rc::clone(function)));
+            .iter()
+            .map(|(name, value)| (name.get().as_str(), value))
     }
 }
@@ -0,0 +1,227 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::mem;
+
+use crate::error::*;
+use crate::gc::*;
+use crate::native::*;
+use crate::value::*;
+use crate::vm::*;
+
+/// A reference that does not keep its target alive.
+///
+/// Once the target has been collected the reference is cleared, and
+/// `deref()` returns `nil`.
+pub struct WeakRef {
+    target: Cell<Option<Value>>,
+}
+
+impl WeakRef {
+    pub(crate) fn new(target: Value) -> Self {
+        Self {
+            target: Cell::new(Some(target)),
+        }
+    }
+
+    /// The target, or `None` if it has been collected.
+    pub fn get(&self) -> Option<Value> {
+        self.target.get()
+    }
+
+    /// Clears the reference if its target is about to be freed.
+    pub(crate) fn sweep(&self) {
+        if let Some(target) = self.target.get()
+            && !is_marked(&target)
+        {
+            self.target.set(None);
+        }
+    }
+}
+
+impl Trace for WeakRef {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl HeapObject for WeakRef {
+    const KIND: ObjectKind = ObjectKind::WeakRef;
+}
+
+impl Debug for WeakRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<weakref {:?}>", self.target.get())
+    }
+}
+
+impl Display for WeakRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("<weakref>")
+    }
+}
+
+/// A map keyed by objects that does not keep its keys alive.
+///
+/// An entry lasts as long as its key is reachable from outside the map.
+/// Its value is kept alive only for that long, so a value that refers back
+/// to its own key does not pin the entry.
+#[derive(Default)]
+pub struct WeakMap {
+    // Keyed by object identity; the key itself is kept for tracing.
+    entries: RefCell<HashMap<usize, (Value, Value)>>,
+}
+
+impl WeakMap {
+    // Memory charged for each entry.
+    const ENTRY_SIZE: usize = mem::size_of::<(usize, (Value, Value))>();
+
+    pub fn get(&self, key: &Value) -> Option<Value> {
+        let id = key.object_id()?;
+        self.entries.borrow().get(&id).map(|(_, value)| *value)
+    }
+
+    /// Sets the value for `key`. Returns `false`, leaving the map
+    /// unchanged, if `key` is not an object.
+    pub(crate) fn set(&self, key: Value, value: Value) -> bool {
+        match key.object_id() {
+            Some(id) => {
+                self.entries.borrow_mut().insert(id, (key, value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn has(&self, key: &Value) -> bool {
+        key.object_id()
+            .is_some_and(|id| self.entries.borrow().contains_key(&id))
+    }
+
+    pub fn remove(&self, key: &Value) -> Option<Value> {
+        let id = key.object_id()?;
+        self.entries.borrow_mut().remove(&id).map(|(_, value)| value)
+    }
+
+    /// Number of entries, including any whose keys have become unreachable
+    /// since the last collection.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Marks the values of entries whose keys are marked.
+    pub(crate) fn trace_entries(&self, tracer: &mut Tracer) {
+        for (key, value) in self.entries.borrow().values() {
+            if is_marked(key) {
+                tracer.mark_value(value);
+            }
+        }
+    }
+
+    /// Drops the entries whose keys are about to be freed, returning the
+    /// bytes this releases.
+    pub(crate) fn sweep(&self) -> usize {
+        let mut entries = self.entries.borrow_mut();
+        let before = entries.len();
+        entries.retain(|_, (key, _)| is_marked(key));
+        (before - entries.len()) * Self::ENTRY_SIZE
+    }
+
+    pub(crate) fn method(name: &str) -> Option<(Arity, BuiltinMethodBody)> {
+        let method: (Arity, BuiltinMethodBody) = match name {
+            "get" => (Arity::Fixed(1), |_, map, args| {
+                Ok(as_weak_map(map).get().get(&args.get(0)).unwrap_or(Value::Nil))
+            }),
+            "set" => (Arity::Fixed(2), |vm, map, args| {
+                let map = as_weak_map(map);
+                let added = !map.get().has(&args.get(0));
+                if added {
+                    vm.reserve(Self::ENTRY_SIZE)?;
+                }
+                if !map.get().set(args.get(0), args.get(1)) {
+                    return Err(key_error());
+                }
+                if added {
+                    vm.heap_mut().grow(map, Self::ENTRY_SIZE);
+                }
+                Ok(Value::Nil)
+            }),
+            "has" => (Arity::Fixed(1), |_, map, args| {
+                Ok(Value::Boolean(as_weak_map(map).get().has(&args.get(0))))
+            }),
+            "remove" => (Arity::Fixed(1), |vm, map, args| {
+                let map = as_weak_map(map);
+                let removed = map.get().remove(&args.get(0));
+                if removed.is_some() {
+                    vm.heap_mut().shrink(map, Self::ENTRY_SIZE);
+                }
+                Ok(removed.unwrap_or(Value::Nil))
+            }),
+            _ => return None,
+        };
+        Some(method)
+    }
+}
+
+fn as_weak_map(receiver: Value) -> Gc<WeakMap> {
+    match receiver {
+        Value::WeakMap(map) => map,
+        _ => unreachable!("WeakMap method bound to {receiver:?}"),
+    }
+}
+
+fn key_error() -> RuntimeError {
+    RuntimeError::new(RuntimeErrorKind::Type, "WeakMap keys must be objects.")
+}
+
+impl Trace for WeakMap {
+    // Entries are traced by the collector once it knows which keys are
+    // reachable; see `WeakMap::trace_entries`.
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl HeapObject for WeakMap {
+    const KIND: ObjectKind = ObjectKind::WeakMap;
+}
+
+impl Debug for WeakMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "<weakmap of {}>", self.len())
+    }
+}
+
+impl Display for WeakMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("<weakmap>")
+    }
+}
+
+/// `weakref(object)`: a weak reference to `object`.
+pub(crate) fn weakref(vm: &mut VM, args: Args) -> Result<Value, RuntimeError> {
+    let target = args.get(0);
+    if target.object_id().is_none() {
+        return Err(RuntimeError::new(
+            RuntimeErrorKind::Type,
+            "Only objects can be weakly referenced.",
+        ));
+    }
+    Ok(Value::Weak(vm.heap_mut().alloc_weak_ref(target)))
+}
+
+/// `deref(ref)`: the target of `ref`, or `nil` once it has been collected.
+pub(crate) fn deref(_vm: &mut VM, args: Args) -> Result<Value, RuntimeError> {
+    match args.get(0) {
+        Value::Weak(weak) => Ok(weak.get().get().unwrap_or(Value::Nil)),
+        _ => Err(RuntimeError::new(
+            RuntimeErrorKind::Type,
+            "Argument 1 to 'deref' must be a weak reference.",
+        )),
+    }
+}
+
+/// `WeakMap()`: a new, empty weak-keyed map.
+pub(crate) fn weak_map(vm: &mut VM, _args: Args) -> Result<Value, RuntimeError> {
+    Ok(Value::WeakMap(vm.heap_mut().alloc_weak_map()))
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{output, Script};
+
+#[test]
+fn closures_see_later_assignments_to_open_upvalues() {
+    assert_eq!(
+        output(
+            "fun outer() {
+               var x = \"before\";
+               fun show() { print x; }
+               x = \"after\";
+               show();
+             }
+             outer();"
+        ),
+        ["after"]
+    );
+}
+
+#[test]
+fn closures_share_a_captured_variable() {
+    assert_eq!(
+        output(
+            "var get;
+             var set;
+             fun make() {
+               var shared = 1;
+               fun getter() { return shared; }
+               fun setter(value) { shared = value; }
+               get = getter;
+               set = setter;
+               print get();
+               set(2);
+               print shared;
+             }
+             make();
+             set(3);
+             print get();"
+        ),
+        ["1", "2", "3"]
+    );
+}
+
+#[test]
+fn upvalues_are_closed_on_return() {
+    // The stack slot that held `count` is reused by later calls, so a
+    // closure still pointing at it would see the wrong value.
+    assert_eq!(
+        output(
+            "fun counter() {
+               var count = 0;
+               fun next() { count = count + 1; return count; }
+               return next;
+             }
+             var a = counter();
+             var b = counter();
+             fun clobber() { var x = 100; var y = 200; return x + y; }
+             clobber();
+             print a();
+             print a();
+             print b();"
+        ),
+        ["1", "2", "1"]
+    );
+}
+
+#[test]
+fn upvalues_are_closed_at_the_end_of_their_block() {
+    assert_eq!(
+        output(
+            "var closures = [];
+             {
+               var x = \"first\";
+               fun first() { return x; }
+               closures.push(first);
+               x = \"closed\";
+             }
+             {
+               var y = \"second\";
+               fun second() { return y; }
+               closures.push(second);
+             }
+             print closures[0]();
+             print closures[1]();"
+        ),
+        ["closed", "second"]
+    );
+}
+
+#[test]
+fn closed_upvalues_survive_collection() {
+    let mut script = Script::new();
+    script
+        .run(
+            "fun counter() {
+               var count = \"a\";
+               fun next() { count = count + \"a\"; return count; }
+               return next;
+             }
+             var next = counter();",
+        )
+        .unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(script.run("print next(); print next();").unwrap(), ["aa", "aaa"]);
+}
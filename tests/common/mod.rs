@@ -0,0 +1,73 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use lox_bytecode::{ErrorCode, InterpretResult, RuntimeError, RuntimeErrorKind, VM};
+
+/// A VM whose printed lines are collected instead of written to stdout.
+pub struct Script {
+    pub vm: VM,
+    printed: Rc<RefCell<Vec<String>>>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        let mut vm = VM::new();
+        // The trace of a long-running script would swamp the test output.
+        #[cfg(feature = "debug_trace_execution")]
+        vm.set_trace_output(std::io::sink());
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&printed);
+        vm.set_print_callback(move |line| sink.borrow_mut().push(line.to_string()));
+        Self { vm, printed }
+    }
+
+    /// Runs `source` and returns the lines it printed.
+    pub fn run(&mut self, source: &str) -> Result<Vec<String>, InterpretResult> {
+        self.vm.interpret(source)?;
+        Ok(self.printed.take())
+    }
+
+    /// Drops the VM and returns the lines printed since the last run,
+    /// including any printed by finalizers on the way out.
+    pub fn finish(self) -> Vec<String> {
+        drop(self.vm);
+        self.printed.take()
+    }
+
+    /// Runs `source`, which must fail at runtime, and returns the error.
+    pub fn runtime_error(&mut self, source: &str) -> RuntimeError {
+        self.printed.take();
+        match self.vm.interpret(source) {
+            Err(InterpretResult::RuntimeError(error)) => error,
+            other => panic!("expected a runtime error from {source:?}, got {other:?}"),
+        }
+    }
+}
+
+/// The lines `source` prints when run on a fresh VM.
+pub fn output(source: &str) -> Vec<String> {
+    match Script::new().run(source) {
+        Ok(lines) => lines,
+        Err(error) => panic!("{source:?} failed: {error:?}"),
+    }
+}
+
+/// Runs `source` on a fresh VM, expecting a runtime error of `kind`, and
+/// returns its message.
+pub fn runtime_error(source: &str, kind: RuntimeErrorKind) -> String {
+    let error = Script::new().runtime_error(source);
+    assert_eq!(error.kind, kind, "{source:?} failed with {:?}", error.message);
+    error.message
+}
+
+/// The error codes `source` fails to compile with.
+pub fn compile_errors(source: &str) -> Vec<ErrorCode> {
+    match Script::new().run(source) {
+        Err(InterpretResult::CompileError(diagnostics)) => {
+            diagnostics.iter().map(|diagnostic| diagnostic.code).collect()
+        }
+        other => panic!("expected {source:?} not to compile, got {other:?}"),
+    }
+}
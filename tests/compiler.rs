@@ -0,0 +1,17 @@
+use lox_bytecode::{Compiler, ErrorCode, Value, VM};
+
+#[test]
+fn compiles_through_the_public_compiler() {
+    let mut vm = VM::new();
+    let function = Compiler::new(&mut vm).compile("var a = 1 + 2;").unwrap();
+    let function = Value::Func(function);
+    assert!(vm.is_alive(&function));
+}
+
+#[test]
+fn reports_diagnostics_through_the_public_compiler() {
+    let mut vm = VM::new();
+    let diagnostics = Compiler::new(&mut vm).compile("1 +;").unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, ErrorCode::ExpectExpression);
+}
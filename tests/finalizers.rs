@@ -0,0 +1,108 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::Script;
+use lox_bytecode::{Limits, RuntimeErrorKind, Value};
+
+const CLASSES: &str = "
+class Resource {
+  init(name) { this.name = name; }
+  deinit() { print \"closing \" + this.name; }
+}";
+
+#[test]
+fn collection_finalizes_unreachable_instances_once() {
+    let mut script = Script::new();
+    script.run(CLASSES).unwrap();
+    script.run("var r = Resource(\"temp\");").unwrap();
+    script.vm.remove_global("r");
+    script.vm.collect_garbage();
+    script.vm.collect_garbage();
+    assert_eq!(script.run("").unwrap(), ["closing temp"]);
+    assert!(script.finish().is_empty());
+}
+
+#[test]
+fn dropping_the_vm_finalizes_reachable_instances() {
+    let mut script = Script::new();
+    script.run(CLASSES).unwrap();
+    script.run("var a = Resource(\"a\"); var b = Resource(\"b\"); var list = [Resource(\"c\")];").unwrap();
+    assert_eq!(script.finish(), ["closing a", "closing b", "closing c"]);
+}
+
+#[test]
+fn dropping_the_vm_finalizes_uncollected_garbage() {
+    let mut script = Script::new();
+    script.run(CLASSES).unwrap();
+    let mut printed = script.run("Resource(\"never collected\");").unwrap();
+    // Unless a collection already found it dead.
+    printed.extend(script.finish());
+    assert_eq!(printed, ["closing never collected"]);
+}
+
+#[test]
+fn revived_instances_are_not_finalized_again() {
+    let mut script = Script::new();
+    script
+        .run(
+            "var saved = nil;
+             class Phoenix { deinit() { print \"deinit\"; saved = this; } }
+             var phoenix = Phoenix();",
+        )
+        .unwrap();
+    script.vm.remove_global("phoenix");
+    script.vm.collect_garbage();
+    assert_eq!(script.run("print saved != nil;").unwrap(), ["deinit", "true"]);
+    assert!(script.finish().is_empty());
+}
+
+#[test]
+fn instances_created_while_dropping_are_not_finalized() {
+    let mut script = Script::new();
+    script
+        .run(
+            "class Spawner { deinit() { print \"spawning\"; Spawner(); } }
+             var s = Spawner();",
+        )
+        .unwrap();
+    assert_eq!(script.finish(), ["spawning"]);
+}
+
+#[test]
+fn pending_finalizers_run_before_live_ones_on_drop() {
+    let mut script = Script::new();
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&errors);
+    script.vm.set_finalizer_error_handler(move |error| sink.borrow_mut().push(error.kind));
+    script
+        .run(
+            "var spin = true;
+             class Slow {
+               init(name) { this.name = name; }
+               deinit() {
+                 while (spin) {}
+                 print \"closing \" + this.name;
+               }
+             }
+             var live = Slow(\"live\");
+             var first = Slow(\"first\");
+             var second = Slow(\"second\");",
+        )
+        .unwrap();
+    // Only now unreachable, whenever the VM collects.
+    script.vm.remove_global("first");
+    script.vm.remove_global("second");
+    script.vm.set_limits(Limits {
+        max_instructions: Some(10_000),
+        ..Limits::default()
+    });
+    // The first finalizer runs out of instructions, which leaves the
+    // second queued.
+    script.vm.collect_garbage();
+    assert_eq!(*errors.borrow(), [RuntimeErrorKind::InstructionLimit]);
+
+    script.vm.set_global("spin", Value::Boolean(false));
+    assert_eq!(script.finish(), ["closing second", "closing live"]);
+}
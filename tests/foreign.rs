@@ -0,0 +1,112 @@
+mod common;
+
+use std::rc::Rc;
+
+use common::Script;
+use lox_bytecode::{Arity, ForeignClass, RuntimeError, RuntimeErrorKind, Value};
+
+struct Counter {
+    count: f64,
+}
+
+fn counter_class() -> Rc<ForeignClass> {
+    let class = Rc::new(ForeignClass::new("Counter"));
+    class.add_method("add", Arity::Fixed(1), |vm, this, args| {
+        let amount = args.number(0)?;
+        let counter = vm.object(this).unwrap();
+        let mut counter = counter.borrow_mut::<Counter>().unwrap();
+        counter.count += amount;
+        Ok(Value::Number(counter.count))
+    });
+    class.add_method("count", Arity::Fixed(0), |vm, this, _| {
+        let counter = vm.object(this).unwrap();
+        Ok(Value::Number(counter.borrow::<Counter>().unwrap().count))
+    });
+    class
+}
+
+/// A script with a `Counter` starting at `count` in the global `c`.
+fn with_counter(count: f64) -> Script {
+    let mut script = Script::new();
+    let counter = script.vm.new_foreign(&counter_class(), Counter { count });
+    script.vm.set_global("c", counter);
+    script
+}
+
+#[test]
+fn methods_are_called_on_the_receiver() {
+    let mut script = with_counter(1.0);
+    assert_eq!(
+        script.run("print c.add(2); print c.count(); print c;").unwrap(),
+        ["3", "3", "Counter instance"]
+    );
+
+    let Some(Value::Foreign(counter)) = script.vm.get_global("c") else {
+        panic!("expected a foreign object");
+    };
+    let counter = script.vm.object(counter).unwrap();
+    assert_eq!(counter.borrow::<Counter>().unwrap().count, 3.0);
+    assert!(counter.get_class().has_method("add"));
+    assert!(!counter.get_class().has_method("reset"));
+}
+
+#[test]
+fn methods_can_be_used_as_values() {
+    let mut script = with_counter(0.0);
+    assert_eq!(
+        script.run("var add = c.add; add(5); add(6); print c.count(); print add;").unwrap(),
+        ["11", "<native fn Counter.add>"]
+    );
+}
+
+#[test]
+fn methods_check_their_arity() {
+    let mut script = with_counter(0.0);
+    for source in ["c.add();", "c.add(1, 2);", "c.count(1);", "var count = c.count; count(1);"] {
+        let error = script.runtime_error(source);
+        assert_eq!(error.kind, RuntimeErrorKind::Arity, "{source:?}: {}", error.message);
+    }
+    // Nothing ran, so the count is untouched.
+    assert_eq!(script.run("print c.count();").unwrap(), ["0"]);
+}
+
+#[test]
+fn method_errors_name_the_method() {
+    let mut script = with_counter(0.0);
+    let error = script.runtime_error("c.add(\"one\");");
+    assert_eq!(error.kind, RuntimeErrorKind::Type);
+    assert_eq!(error.message, "Argument 1 to 'Counter.add' must be a number.");
+    assert_eq!(error.trace[0].function, "Counter.add");
+
+    let error = script.runtime_error("c.reset();");
+    assert_eq!(error.kind, RuntimeErrorKind::UndefinedProperty);
+    let error = script.runtime_error("print c.total;");
+    assert_eq!(error.kind, RuntimeErrorKind::UndefinedProperty);
+}
+
+#[test]
+fn data_is_only_borrowed_as_its_own_type() {
+    let mut script = Script::new();
+    let class = Rc::new(ForeignClass::new("Name"));
+    class.add_method("rename", Arity::Fixed(1), |vm, this, args| {
+        let name = args.string(0)?;
+        let object = vm.object(this).unwrap();
+        // Asking for the wrong type gives nothing rather than panicking.
+        if object.borrow_mut::<Counter>().is_some() {
+            return Err(RuntimeError::new(RuntimeErrorKind::Native, "not a counter"));
+        }
+        *object.borrow_mut::<String>().unwrap() = name;
+        Ok(Value::Nil)
+    });
+    let name = script.vm.new_foreign(&class, String::from("before"));
+    script.vm.set_global("n", name);
+    script.run("n.rename(\"after\");").unwrap();
+
+    let Value::Foreign(name) = name else {
+        panic!("expected a foreign object");
+    };
+    let name = script.vm.object(name).unwrap();
+    assert_eq!(*name.borrow::<String>().unwrap(), "after");
+    assert!(name.borrow::<Counter>().is_none());
+    assert!(name.borrow_mut::<f64>().is_none());
+}
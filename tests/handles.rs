@@ -0,0 +1,131 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lox_bytecode::{Arity, InterpretResult, RuntimeErrorKind, Value, VM};
+
+fn vm() -> VM {
+    let mut vm = VM::new();
+    vm.set_print_callback(|_| {});
+    vm
+}
+
+#[test]
+fn live_handles_can_be_read() {
+    let mut vm = vm();
+    vm.interpret("var greeting = \"hello\";").unwrap();
+    let greeting = vm.get_global("greeting").unwrap();
+    let Value::Str(text) = greeting else {
+        panic!("expected a string, got {greeting:?}");
+    };
+    assert_eq!(vm.object(text).unwrap().as_str(), "hello");
+    assert_eq!(greeting.display(&vm).to_string(), "hello");
+    assert!(vm.is_alive(&greeting));
+}
+
+#[test]
+fn collected_handles_are_refused() {
+    let mut vm = vm();
+    vm.interpret("var a = \"only \" + \"here\"; fun f() { return 1; }").unwrap();
+    let a = vm.get_global("a").unwrap();
+    let f = vm.get_global("f").unwrap();
+    vm.remove_global("a");
+    vm.remove_global("f");
+    vm.collect_garbage();
+
+    let Value::Str(text) = a else {
+        panic!("expected a string, got {a:?}");
+    };
+    assert!(vm.object(text).is_none());
+    assert!(!vm.is_alive(&a));
+    assert_eq!(a.display(&vm).to_string(), "<dead object>");
+
+    let error = vm.call_function(&f, &[]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::InvalidHandle);
+
+    // The VM is still usable afterwards.
+    vm.interpret("var b = 1 + 2;").unwrap();
+    assert_eq!(vm.get_global("b"), Some(Value::Number(3.0)));
+}
+
+#[test]
+fn handles_are_refused_after_their_address_is_reused() {
+    let mut vm = vm();
+    vm.interpret("var a = [1];").unwrap();
+    let a = vm.get_global("a").unwrap();
+    vm.remove_global("a");
+    vm.collect_garbage();
+
+    // Plenty of new lists, one of which is likely to land where `a` was.
+    vm.interpret("var lists = []; for (var i = 0; i < 1000; i = i + 1) lists.push([i]);")
+        .unwrap();
+    let Value::List(list) = a else {
+        panic!("expected a list, got {a:?}");
+    };
+    assert!(vm.object(list).is_none());
+    assert!(!vm.is_alive(&a));
+    assert_eq!(a.display(&vm).to_string(), "<dead object>");
+}
+
+#[test]
+fn pinned_handles_survive_collection() {
+    let mut vm = vm();
+    vm.interpret("var a = \"pinned \" + \"text\";").unwrap();
+    let a = vm.get_global("a").unwrap();
+    vm.pin(&a);
+    vm.remove_global("a");
+    vm.collect_garbage();
+    assert_eq!(a.display(&vm).to_string(), "pinned text");
+    vm.unpin(&a);
+    vm.collect_garbage();
+    assert!(!vm.is_alive(&a));
+}
+
+#[test]
+fn handles_from_another_vm_are_refused() {
+    let mut first = vm();
+    first.interpret("var list = [1, 2];").unwrap();
+    let list = first.get_global("list").unwrap();
+
+    let mut second = vm();
+    assert!(!second.is_alive(&list));
+    assert_eq!(list.display(&second).to_string(), "<dead object>");
+    let error = second.call_function(&list, &[]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::InvalidHandle);
+
+    drop(first);
+    assert!(!second.is_alive(&list));
+}
+
+#[test]
+fn natives_cannot_return_stale_handles() {
+    let mut vm = vm();
+    let stash: Rc<Cell<Option<Value>>> = Rc::new(Cell::new(None));
+    let keep = Rc::clone(&stash);
+    vm.define_native_fn("keep", Arity::Fixed(1), move |_, args| {
+        keep.set(Some(args.get(0)));
+        Ok(Value::Nil)
+    });
+    let give = Rc::clone(&stash);
+    vm.define_native_fn("give", Arity::Fixed(0), move |_, _| {
+        Ok(give.get().unwrap_or(Value::Nil))
+    });
+
+    vm.interpret("keep([1, 2]);").unwrap();
+    vm.collect_garbage();
+    let error = vm.interpret("print give();").unwrap_err();
+    let InterpretResult::RuntimeError(error) = error else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(error.kind, RuntimeErrorKind::InvalidHandle);
+}
+
+#[test]
+#[should_panic(expected = "not alive")]
+fn setting_a_global_to_a_stale_handle_panics() {
+    let mut vm = vm();
+    vm.interpret("var a = \"short \" + \"lived\";").unwrap();
+    let a = vm.get_global("a").unwrap();
+    vm.remove_global("a");
+    vm.collect_garbage();
+    vm.set_global("b", a);
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::Script;
+use lox_bytecode::{Arity, RuntimeErrorKind, Value};
+
+const GREETER: &str = "
+class Greeter {
+  init(name) { this.name = name; }
+  greet(who, mark) { return this.name + \" greets \" + who + mark; }
+}
+var greeter = Greeter(\"ann\");";
+
+#[test]
+fn methods_can_be_invoked_with_arguments() {
+    let mut script = Script::new();
+    script.run(GREETER).unwrap();
+    let greeter = script.vm.get_global("greeter").unwrap();
+    let who = Value::Str(script.vm.intern("bob"));
+    let mark = Value::Str(script.vm.intern("!"));
+    let greeting = script.vm.invoke_method(&greeter, "greet", &[who, mark]).unwrap();
+    assert_eq!(greeting.display(&script.vm).to_string(), "ann greets bob!");
+}
+
+#[test]
+fn invoking_prefers_a_callable_field() {
+    let mut script = Script::new();
+    let source = format!("{GREETER} fun wave(who, mark) {{ return \"waves\"; }}");
+    script.run(&format!("{source} greeter.greet = wave;")).unwrap();
+    let greeter = script.vm.get_global("greeter").unwrap();
+    let result = script.vm.invoke_method(&greeter, "greet", &[Value::Nil, Value::Nil]).unwrap();
+    assert_eq!(result.display(&script.vm).to_string(), "waves");
+}
+
+#[test]
+fn invoking_reports_bad_calls() {
+    let mut script = Script::new();
+    script.run(GREETER).unwrap();
+    let greeter = script.vm.get_global("greeter").unwrap();
+
+    let error = script.vm.invoke_method(&greeter, "greet", &[Value::Nil]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::Arity);
+    let error = script.vm.invoke_method(&greeter, "leave", &[]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::UndefinedProperty);
+    let error = script.vm.invoke_method(&Value::Number(1.0), "greet", &[]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::Type);
+    // An error inside the method unwinds only the call.
+    let error = script
+        .vm
+        .invoke_method(&greeter, "greet", &[Value::Number(1.0), Value::Nil])
+        .unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::Type);
+    assert_eq!(error.trace.len(), 1);
+    assert_eq!(error.trace[0].function, "greet");
+
+    assert_eq!(script.run("print greeter.greet(\"cy\", \".\");").unwrap(), ["ann greets cy."]);
+}
+
+/// A script with `attempt(f, x)`, which calls `f(x)` from Rust and returns
+/// the error message instead of failing if the call does.
+fn with_attempt() -> Script {
+    let mut script = Script::new();
+    script.vm.define_native_fn("attempt", Arity::Fixed(2), |vm, args| {
+        match vm.call_function(&args.get(0), &[args.get(1)]) {
+            Ok(value) => Ok(value),
+            Err(error) => Ok(Value::Str(vm.intern(&error.message))),
+        }
+    });
+    script.vm.define_native_fn("call", Arity::Fixed(2), |vm, args| {
+        vm.call_function(&args.get(0), &[args.get(1)])
+    });
+    script
+}
+
+#[test]
+fn natives_can_call_back_into_lox() {
+    let mut script = with_attempt();
+    assert_eq!(
+        script
+            .run("fun twice(x) { return x * 2; } print call(twice, 4); print attempt(twice, 5);")
+            .unwrap(),
+        ["8", "10"]
+    );
+}
+
+#[test]
+fn errors_in_nested_calls_can_be_caught_by_the_native() {
+    let mut script = with_attempt();
+    let printed = script
+        .run(
+            "fun bad(x) { return x + nil; }
+             fun outer() {
+               var before = \"kept\";
+               var message = attempt(bad, 1);
+               var after = \"also kept\";
+               print message;
+               print before;
+               print after;
+             }
+             outer();
+             print \"done\";",
+        )
+        .unwrap();
+    assert_eq!(
+        printed,
+        ["Operands must be two numbers or two strings.", "kept", "also kept", "done"]
+    );
+}
+
+#[test]
+fn errors_in_nested_calls_propagate_through_the_native() {
+    let mut script = with_attempt();
+    let error = script.runtime_error(
+        "fun bad(x) {\n  return x + nil;\n}\nfun outer() {\n  return call(bad, 1);\n}\nouter();",
+    );
+    assert_eq!(error.kind, RuntimeErrorKind::Type);
+    let functions: Vec<&str> = error.trace.iter().map(|frame| frame.function.as_str()).collect();
+    assert_eq!(functions[..3], ["bad", "call", "outer"]);
+    assert_eq!(error.trace[0].line, 2);
+    assert_eq!(error.trace[2].line, 5);
+
+    // The VM is usable afterwards.
+    assert_eq!(script.run("fun id(x) { return x; } print call(id, 7);").unwrap(), ["7"]);
+}
+
+#[test]
+fn globals_lists_scripts_and_natives() {
+    let mut script = Script::new();
+    script.run("var answer = 42; fun f() {} class C {}").unwrap();
+    let names: Vec<&str> = script.vm.globals().map(|(name, _)| name).collect();
+    for name in ["C", "WeakMap", "answer", "clock", "deref", "f", "weakref"] {
+        assert!(names.contains(&name), "{name} missing from {names:?}");
+    }
+    let answer = script.vm.globals().find(|(name, _)| *name == "answer").map(|(_, value)| *value);
+    assert_eq!(answer, Some(Value::Number(42.0)));
+
+    script.vm.remove_global("answer");
+    assert!(script.vm.globals().all(|(name, _)| name != "answer"));
+}
+
+#[test]
+fn set_global_defines_and_overwrites() {
+    let mut script = Script::new();
+    script.vm.set_global("limit", Value::Number(3.0));
+    assert_eq!(script.run("print limit;").unwrap(), ["3"]);
+
+    script.run("var name = \"a\" + \"b\";").unwrap();
+    let name = script.vm.get_global("name").unwrap();
+    script.vm.set_global("limit", name);
+    assert_eq!(script.run("print limit; limit = 4;").unwrap(), ["ab"]);
+    assert_eq!(script.vm.get_global("limit"), Some(Value::Number(4.0)));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::Script;
+use lox_bytecode::{Arity, RuntimeErrorKind, Value};
+
+#[test]
+fn interrupt_sent_while_idle_stops_the_next_call() {
+    let mut script = Script::new();
+    script.vm.interrupt_handle().interrupt();
+    let error = script.runtime_error("print 1;");
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    // The flag is cleared once the interrupted call returns.
+    assert!(!script.vm.interrupt_handle().is_interrupted());
+    assert_eq!(script.run("print 2;").unwrap(), ["2"]);
+}
+
+#[test]
+fn interrupt_stops_a_running_loop() {
+    let mut script = Script::new();
+    let handle = script.vm.interrupt_handle();
+    script.vm.define_native_fn("stop", Arity::Fixed(0), move |_, _| {
+        handle.interrupt();
+        Ok(Value::Nil)
+    });
+    let error = script.runtime_error("var i = 0; while (true) { i = i + 1; if (i == 100) stop(); }");
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    assert_eq!(script.vm.get_global("i"), Some(Value::Number(100.0)));
+    assert!(!script.vm.interrupt_handle().is_interrupted());
+}
+
+#[test]
+fn interrupt_reaches_the_outer_script_through_a_nested_call() {
+    let mut script = Script::new();
+    let handle = script.vm.interrupt_handle();
+    script.vm.define_native_fn("call_interrupted", Arity::Fixed(1), move |vm, args| {
+        handle.interrupt();
+        let error = vm.call_function(&args.get(0), &[]).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+        // Still set: the host has not been reached yet.
+        assert!(handle.is_interrupted());
+        // Swallow the error; the outer script must stop anyway.
+        Ok(Value::Nil)
+    });
+    let error = script.runtime_error(
+        "var reached = false;
+         fun inner() { print \"inner\"; }
+         call_interrupted(inner);
+         reached = true;",
+    );
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    assert_eq!(script.vm.get_global("reached"), Some(Value::Boolean(false)));
+    assert!(!script.vm.interrupt_handle().is_interrupted());
+    assert_eq!(script.run("print \"after\";").unwrap(), ["after"]);
+}
+
+#[test]
+fn interrupt_holds_across_calls_from_a_native_called_by_the_host() {
+    let mut script = Script::new();
+    script.run("fun one() { return 1; }").unwrap();
+    let handle = script.vm.interrupt_handle();
+    script.vm.define_native_fn("call_twice", Arity::Fixed(1), move |vm, args| {
+        handle.interrupt();
+        for _ in 0..2 {
+            let error = vm.call_function(&args.get(0), &[]).unwrap_err();
+            assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+            assert!(handle.is_interrupted());
+        }
+        Ok(Value::Nil)
+    });
+    let one = script.vm.get_global("one").unwrap();
+    let error = script.vm.call_global("call_twice", &[one]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    assert!(!script.vm.interrupt_handle().is_interrupted());
+    assert_eq!(script.vm.call_function(&one, &[]).unwrap(), Value::Number(1.0));
+}
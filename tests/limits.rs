@@ -0,0 +1,221 @@
+mod common;
+
+use std::time::Duration;
+
+use common::Script;
+use lox_bytecode::{Arity, Limits, RuntimeErrorKind, Value};
+
+/// A script VM with `limits` and an `attempt(f)` native that calls `f`
+/// from Rust and returns the kind of any error as a string.
+fn limited(limits: Limits) -> Script {
+    let mut script = Script::new();
+    script.vm.set_limits(limits);
+    script.vm.define_native_fn("attempt", Arity::Fixed(1), |vm, args| {
+        match vm.call_function(&args.get(0), &[]) {
+            Ok(value) => Ok(value),
+            Err(error) => Ok(Value::Str(vm.intern(&format!("{:?}", error.kind)))),
+        }
+    });
+    script
+}
+
+/// Checks that the VM still runs scripts, host calls and nested calls
+/// correctly, which needs the stack and frame bases restored.
+fn assert_usable(script: &mut Script) {
+    assert_eq!(
+        script
+            .run(
+                "fun add(a, b) { var sum = a + b; return sum; }
+                 fun nested() { var x = 40; return add(x, 2); }
+                 print add(1, 2);
+                 print attempt(nested);"
+            )
+            .unwrap(),
+        ["3", "42"]
+    );
+    let result = script.vm.call_global("add", &[Value::Number(2.0), Value::Number(3.0)]);
+    assert_eq!(result.unwrap(), Value::Number(5.0));
+}
+
+const SPIN: &str = "fun runaway() { while (true) {} }";
+const RECURSE: &str = "fun runaway() { runaway(); }";
+const DEEP_LOCALS: &str = "fun runaway() { var a = 1; var b = 2; var c = 3; runaway(); }";
+const GROW: &str = "fun runaway() { var s = \"0123456789abcdef\"; while (true) s = s + s; }";
+
+fn instructions() -> Limits {
+    Limits {
+        max_instructions: Some(10_000),
+        ..Limits::default()
+    }
+}
+
+fn timeout() -> Limits {
+    Limits {
+        timeout: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    }
+}
+
+fn frames() -> Limits {
+    Limits {
+        max_frames: 64,
+        ..Limits::default()
+    }
+}
+
+fn stack() -> Limits {
+    Limits {
+        max_frames: 100_000,
+        max_stack: Some(500),
+        ..Limits::default()
+    }
+}
+
+fn memory() -> Limits {
+    Limits {
+        max_memory: Some(1 << 20),
+        ..Limits::default()
+    }
+}
+
+/// Runs `runaway` at the top level and then from a nested call, checking
+/// the error kind each time and that the VM is usable afterwards.
+fn check(limits: Limits, runaway: &str, kind: RuntimeErrorKind) {
+    let mut script = limited(limits);
+    let error = script.runtime_error(&format!("{runaway} runaway();"));
+    assert_eq!(error.kind, kind, "{}", error.message);
+    assert_usable(&mut script);
+
+    let error = script.vm.call_global("runaway", &[]).unwrap_err();
+    assert_eq!(error.kind, kind, "{}", error.message);
+    assert_usable(&mut script);
+
+    // Inside a call from a native, the limit stops the nested run. Budgets
+    // for instructions and time are shared with the outer run, so it runs
+    // out too; the other limits leave it to carry on.
+    let source = "var before = \"before\";
+                  var result = attempt(runaway);
+                  var after = before + \" and after\";";
+    match kind {
+        RuntimeErrorKind::InstructionLimit | RuntimeErrorKind::Timeout => {
+            let error = script.runtime_error(&format!("{source} while (true) {{}}"));
+            assert_eq!(error.kind, kind, "{}", error.message);
+        }
+        _ => {
+            script.run(source).unwrap();
+            let result = script.vm.get_global("result").unwrap();
+            assert_eq!(result.display(&script.vm).to_string(), format!("{kind:?}"));
+            let after = script.vm.get_global("after").unwrap();
+            assert_eq!(after.display(&script.vm).to_string(), "before and after");
+        }
+    }
+    assert_usable(&mut script);
+}
+
+#[test]
+fn instruction_limit() {
+    check(instructions(), SPIN, RuntimeErrorKind::InstructionLimit);
+}
+
+#[test]
+fn instruction_budget_restarts_with_each_host_call() {
+    let mut script = limited(instructions());
+    for _ in 0..5 {
+        script.run("for (var i = 0; i < 500; i = i + 1) {}").unwrap();
+    }
+}
+
+#[test]
+fn timeout_limit() {
+    check(timeout(), SPIN, RuntimeErrorKind::Timeout);
+}
+
+#[test]
+fn frame_limit() {
+    check(frames(), RECURSE, RuntimeErrorKind::StackOverflow);
+}
+
+#[test]
+fn stack_limit() {
+    check(stack(), DEEP_LOCALS, RuntimeErrorKind::StackLimit);
+}
+
+#[test]
+fn memory_limit() {
+    check(memory(), GROW, RuntimeErrorKind::OutOfMemory);
+}
+
+#[test]
+fn concatenation_fails_before_allocating_past_the_limit() {
+    let limit = 1 << 20;
+    let mut script = limited(memory());
+    // The doubling that would take the string to a full megabyte must
+    // fail without allocating it.
+    let error = script.runtime_error(
+        "var s = \"0123456789abcdef\";
+         while (true) s = s + s;",
+    );
+    assert_eq!(error.kind, RuntimeErrorKind::OutOfMemory, "{}", error.message);
+    let stats = script.vm.heap_stats();
+    assert!(stats.peak_bytes <= limit, "peak of {} bytes", stats.peak_bytes);
+    assert_usable(&mut script);
+}
+
+#[test]
+fn budgets_cover_natives_called_straight_from_the_host() {
+    let mut script = limited(instructions());
+    script.vm.define_native_fn("repeat", Arity::Fixed(1), |vm, args| {
+        for _ in 0..1000 {
+            vm.call_function(&args.get(0), &[])?;
+        }
+        Ok(Value::Nil)
+    });
+    script.run("fun work() { for (var i = 0; i < 20; i = i + 1) {} }").unwrap();
+    let work = script.vm.get_global("work").unwrap();
+
+    let error = script.vm.call_global("repeat", &[work]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::InstructionLimit);
+    let error = script.runtime_error("repeat(work);");
+    assert_eq!(error.kind, RuntimeErrorKind::InstructionLimit);
+    assert_usable(&mut script);
+
+    let mut script = limited(timeout());
+    script.vm.define_native_fn("forever", Arity::Fixed(1), |vm, args| {
+        loop {
+            vm.call_function(&args.get(0), &[])?;
+        }
+    });
+    script.run("fun work() { for (var i = 0; i < 20; i = i + 1) {} }").unwrap();
+    let work = script.vm.get_global("work").unwrap();
+    let error = script.vm.call_global("forever", &[work]).unwrap_err();
+    assert_eq!(error.kind, RuntimeErrorKind::Timeout);
+}
+
+#[test]
+fn collections_fail_before_allocating_past_the_limit() {
+    let limit = 1 << 16;
+    for source in [
+        // Each slice copies a list that keeps growing.
+        "var l = [0]; while (true) { l.push(l[0]); l = l[0:]; }",
+        "var m = {}; for (var i = 0; i < 200; i = i + 1) m[i] = i;
+         var all = []; while (true) all.push(m.keys());",
+        "var m = {}; for (var i = 0; i < 200; i = i + 1) m[i] = i;
+         var all = []; while (true) all.push(m.entries());",
+        "var m = {}; var i = 0; while (true) { m[i] = i; i = i + 1; }",
+        "var all = []; while (true) all.push([all, all, all, all, all, all, all, all]);",
+        "var all = []; while (true) all.push({1: all, 2: all, 3: all, 4: all});",
+    ] {
+        let mut script = limited(Limits {
+            max_memory: Some(limit),
+            ..Limits::default()
+        });
+        let error = script.runtime_error(source);
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfMemory, "{source:?}: {}", error.message);
+        let stats = script.vm.heap_stats();
+        assert!(stats.peak_bytes <= limit, "{source:?} peaked at {} bytes", stats.peak_bytes);
+        for name in ["l", "m", "all"] {
+            script.vm.remove_global(name);
+        }
+        assert_usable(&mut script);
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::{output, runtime_error, Script};
+use lox_bytecode::RuntimeErrorKind;
+
+#[test]
+fn indexing_counts_from_either_end() {
+    assert_eq!(
+        output("var l = [1, 2, 3]; print l[0]; print l[2]; print l[-1]; print l[-3];"),
+        ["1", "3", "3", "1"]
+    );
+    assert_eq!(output("var l = [1, 2, 3]; l[-1] = 9; l[0] = 7; print l;"), ["[7, 2, 9]"]);
+}
+
+#[test]
+fn out_of_range_indices_are_index_errors() {
+    for source in [
+        "[1, 2, 3][3];",
+        "[1, 2, 3][-4];",
+        "[][0];",
+        "var l = [1]; l[1] = 2;",
+        "var l = [1]; l[-2] = 2;",
+    ] {
+        let message = runtime_error(source, RuntimeErrorKind::Index);
+        assert!(message.contains("out of bounds"), "{source:?}: {message}");
+    }
+}
+
+#[test]
+fn indices_must_be_integers() {
+    for source in ["[1][0.5];", "[1][\"0\"];", "[1][nil];", "var l = [1]; l[true] = 2;"] {
+        let message = runtime_error(source, RuntimeErrorKind::Type);
+        assert_eq!(message, "List index must be an integer.", "{source:?}");
+    }
+    runtime_error("var n = 1; n[0];", RuntimeErrorKind::Type);
+}
+
+#[test]
+fn slices_clamp_their_bounds() {
+    assert_eq!(
+        output(
+            "var l = [1, 2, 3, 4];
+             print l[1:3];
+             print l[:2];
+             print l[2:];
+             print l[:];
+             print l[-2:];
+             print l[:-1];
+             print l[-10:10];
+             print l[3:1];
+             print l[4:];"
+        ),
+        ["[2, 3]", "[1, 2]", "[3, 4]", "[1, 2, 3, 4]", "[3, 4]", "[1, 2, 3]", "[1, 2, 3, 4]", "[]", "[]"]
+    );
+}
+
+#[test]
+fn slice_bounds_must_be_integers() {
+    let message = runtime_error("[1, 2][0.5:];", RuntimeErrorKind::Type);
+    assert_eq!(message, "Slice bound must be an integer.");
+    runtime_error("[1, 2][:\"1\"];", RuntimeErrorKind::Type);
+    runtime_error("var m = {}; m[0:1];", RuntimeErrorKind::Type);
+}
+
+#[test]
+fn methods_work() {
+    assert_eq!(
+        output(
+            "var l = [1, 2];
+             l.push(3);
+             print l.pop();
+             l.insert(0, 0);
+             l.insert(3, 9);
+             print l;
+             print l.remove(-1);
+             print l.len();
+             print l.contains(2);
+             print l.contains(5);"
+        ),
+        ["3", "[0, 1, 2, 9]", "9", "3", "true", "false"]
+    );
+}
+
+#[test]
+fn methods_check_their_arity() {
+    for source in [
+        "[].push();",
+        "[].push(1, 2);",
+        "[1].pop(1);",
+        "[].insert(0);",
+        "[1].remove();",
+        "[].len(1);",
+        "[].contains();",
+    ] {
+        runtime_error(source, RuntimeErrorKind::Arity);
+    }
+}
+
+#[test]
+fn methods_check_their_arguments() {
+    runtime_error("[].pop();", RuntimeErrorKind::Index);
+    runtime_error("[].insert(1, 0);", RuntimeErrorKind::Index);
+    runtime_error("[1].remove(1);", RuntimeErrorKind::Index);
+    runtime_error("[].insert(\"0\", 0);", RuntimeErrorKind::Type);
+    runtime_error("[1].remove(0.5);", RuntimeErrorKind::Type);
+    runtime_error("[].nope();", RuntimeErrorKind::UndefinedProperty);
+}
+
+#[test]
+fn calling_a_method_binds_nothing() {
+    let mut script = Script::new();
+    script.run("var l = [];").unwrap();
+    let natives = script.vm.heap_stats().natives;
+    script.run("for (var i = 0; i < 100; i = i + 1) l.push(l.len());").unwrap();
+    assert_eq!(script.vm.heap_stats().natives, natives);
+
+    let error = script.runtime_error("l.insert(\"0\", 0);");
+    assert_eq!(error.message, "List index must be an integer.");
+    assert_eq!(error.trace[0].function, "List.insert");
+}
+
+#[test]
+fn methods_can_be_used_as_values() {
+    assert_eq!(
+        output("var l = [1]; var push = l.push; push(2); print l; print push;"),
+        ["[1, 2]", "<native fn List.push>"]
+    );
+}
+
+#[test]
+fn cycles_print_as_ellipses() {
+    assert_eq!(
+        output("var a = [1]; var b = [a]; a.push(b); print a; print b;"),
+        ["[1, [[...]]]", "[[1, [...]]]"]
+    );
+    assert_eq!(output("var a = []; a.push(a); print a;"), ["[[...]]"]);
+    // Sharing without a cycle prints in full.
+    assert_eq!(output("var a = [1]; print [a, a];"), ["[[1], [1]]"]);
+}
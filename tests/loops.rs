@@ -0,0 +1,163 @@
+mod common;
+
+use common::{compile_errors, output};
+use lox_bytecode::ErrorCode;
+
+#[test]
+fn labeled_break_leaves_the_outer_loop() {
+    assert_eq!(
+        output(
+            "outer: for (var a = 0; a < 3; a = a + 1) {
+               for (var b = 0; b < 3; b = b + 1) {
+                 if (a == 1 and b == 1) break outer;
+                 print a * 10 + b;
+               }
+             }
+             print \"after\";"
+        ),
+        ["0", "1", "2", "10", "after"]
+    );
+}
+
+#[test]
+fn labeled_continue_moves_to_the_next_outer_iteration() {
+    assert_eq!(
+        output(
+            "var a = 0;
+             outer: while (a < 3) {
+               a = a + 1;
+               var b = 0;
+               while (true) {
+                 b = b + 1;
+                 if (b == 2) continue outer;
+                 print a * 10 + b;
+               }
+             }"
+        ),
+        ["11", "21", "31"]
+    );
+}
+
+#[test]
+fn unlabeled_break_and_continue_affect_the_innermost_loop() {
+    assert_eq!(
+        output(
+            "for (var a = 0; a < 2; a = a + 1) {
+               for (var b = 0; b < 5; b = b + 1) {
+                 if (b == 1) continue;
+                 if (b == 3) break;
+                 print a * 10 + b;
+               }
+             }"
+        ),
+        ["0", "2", "10", "12"]
+    );
+}
+
+#[test]
+fn continue_runs_the_increment_clause() {
+    assert_eq!(
+        output(
+            "for (var i = 0; i < 5; i = i + 1) {
+               if (i % 2 == 0) continue;
+               print i;
+             }"
+        ),
+        ["1", "3"]
+    );
+    // Locals declared before `continue` are popped before the increment.
+    assert_eq!(
+        output(
+            "var total = 0;
+             for (var i = 0; i < 4; i = i + 1) {
+               var a = i;
+               var b = a * 2;
+               if (i < 2) continue;
+               total = total + b;
+             }
+             print total;"
+        ),
+        ["10"]
+    );
+}
+
+#[test]
+fn closures_capture_loop_locals_before_break() {
+    assert_eq!(
+        output(
+            "var saved = nil;
+             for (var i = 0; i < 10; i = i + 1) {
+               var captured = i * 2;
+               fun show() { return captured; }
+               if (i == 3) { saved = show; break; }
+             }
+             print saved();"
+        ),
+        ["6"]
+    );
+    assert_eq!(
+        output(
+            "var fns = [];
+             outer: while (true) {
+               var y = \"outer\";
+               while (true) {
+                 var z = \"inner\";
+                 fun f() { return y + \" \" + z; }
+                 fns.push(f);
+                 if (fns.len() == 2) break outer;
+                 break;
+               }
+             }
+             print fns[0]();
+             print fns[1]();"
+        ),
+        ["outer inner", "outer inner"]
+    );
+}
+
+#[test]
+fn closures_capture_each_iteration_before_continue() {
+    assert_eq!(
+        output(
+            "var fns = [];
+             for (var i = 0; i < 3; i = i + 1) {
+               var captured = i;
+               fun get() { return captured; }
+               fns.push(get);
+               continue;
+             }
+             print fns[0]();
+             print fns[1]();
+             print fns[2]();"
+        ),
+        ["0", "1", "2"]
+    );
+}
+
+#[test]
+fn unknown_labels_do_not_compile() {
+    assert_eq!(
+        compile_errors("while (true) { break nowhere; }"),
+        [ErrorCode::UndefinedLabel]
+    );
+    assert_eq!(
+        compile_errors("outer: while (true) { continue inner; }"),
+        [ErrorCode::UndefinedLabel]
+    );
+    // A label is only in scope inside its own loop.
+    assert_eq!(
+        compile_errors("done: while (false) {} while (true) { break done; }"),
+        [ErrorCode::UndefinedLabel]
+    );
+}
+
+#[test]
+fn break_and_continue_outside_loops_do_not_compile() {
+    assert_eq!(compile_errors("break;"), [ErrorCode::BreakOutsideLoop]);
+    assert_eq!(compile_errors("continue;"), [ErrorCode::ContinueOutsideLoop]);
+    // A function body starts outside any loop.
+    assert_eq!(
+        compile_errors("while (true) { fun f() { break; } }"),
+        [ErrorCode::BreakOutsideLoop]
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{output, runtime_error};
+use lox_bytecode::RuntimeErrorKind;
+
+#[test]
+fn keys_are_compared_by_value() {
+    assert_eq!(
+        output(
+            "var m = {\"a\": 1, 2: \"two\", true: 3, nil: 4};
+             print m[\"a\"];
+             print m[\"a\" + \"\"];
+             print m[1 + 1];
+             print m[true];
+             print m[nil];
+             m[0] = \"zero\";
+             print m[-0];"
+        ),
+        ["1", "1", "two", "3", "4", "zero"]
+    );
+}
+
+#[test]
+fn assignment_replaces_in_place() {
+    assert_eq!(
+        output("var m = {\"a\": 1, \"b\": 2}; m[\"a\"] = 3; m[\"c\"] = 4; print m;"),
+        ["{a: 3, b: 2, c: 4}"]
+    );
+}
+
+#[test]
+fn missing_keys_are_key_errors() {
+    let message = runtime_error("var m = {\"a\": 1}; m[\"b\"];", RuntimeErrorKind::Key);
+    assert_eq!(message, "Key b not found in map.");
+    runtime_error("var m = {}; m[0];", RuntimeErrorKind::Key);
+}
+
+#[test]
+fn non_hashable_keys_are_refused() {
+    for source in [
+        "var m = {}; m[[1]];",
+        "var m = {}; m[{}] = 1;",
+        "var m = {[1]: 2};",
+        "fun f() {} var m = {}; m[f] = 1;",
+        "class C {} var m = {}; m[C()] = 1;",
+        "var m = {}; m.has([]);",
+        "var m = {}; m.remove({});",
+    ] {
+        let message = runtime_error(source, RuntimeErrorKind::Type);
+        assert_eq!(message, "Map keys must be strings, numbers, booleans or nil.", "{source:?}");
+    }
+    let message = runtime_error("var m = {}; m[0 / 0] = 1;", RuntimeErrorKind::Type);
+    assert_eq!(message, "Map keys can't be NaN.");
+}
+
+#[test]
+fn methods_work() {
+    assert_eq!(
+        output(
+            "var m = {\"a\": 1, \"b\": 2, \"c\": 3};
+             print m.keys();
+             print m.values();
+             print m.entries();
+             print m.has(\"b\");
+             print m.remove(\"b\");
+             print m.remove(\"b\");
+             print m.has(\"b\");
+             print m.len();
+             m[\"b\"] = 4;
+             print m;"
+        ),
+        [
+            "[a, b, c]",
+            "[1, 2, 3]",
+            "[[a, 1], [b, 2], [c, 3]]",
+            "true",
+            "2",
+            "nil",
+            "false",
+            "2",
+            "{a: 1, c: 3, b: 4}",
+        ]
+    );
+}
+
+#[test]
+fn methods_check_their_arity() {
+    for source in [
+        "({}).keys(1);",
+        "({}).values(1);",
+        "({}).entries(1);",
+        "({}).has();",
+        "({}).has(1, 2);",
+        "({}).remove();",
+        "({}).len(1);",
+    ] {
+        runtime_error(source, RuntimeErrorKind::Arity);
+    }
+    runtime_error("({}).nope();", RuntimeErrorKind::UndefinedProperty);
+}
+
+#[test]
+fn cycles_print_as_ellipses() {
+    assert_eq!(output("var m = {}; m[\"self\"] = m; print m;"), ["{self: {...}}"]);
+    assert_eq!(
+        output("var m = {\"x\": 1}; var l = [m]; m[\"l\"] = l; print m; print l;"),
+        ["{x: 1, l: [{...}]}", "[{x: 1, l: [...]}]"]
+    );
+}
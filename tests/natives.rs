@@ -0,0 +1,97 @@
+mod common;
+
+use std::rc::Rc;
+
+use common::Script;
+use lox_bytecode::{Args, Arity, NativeFunc, RuntimeError, RuntimeErrorKind, Value, VM};
+
+fn with_natives() -> Script {
+    let mut script = Script::new();
+    script.vm.define_native_fn("add", Arity::Fixed(2), |_, args| {
+        Ok(Value::Number(args.number(0)? + args.number(1)?))
+    });
+    script.vm.define_native_fn("count", Arity::Variadic(1), |_, args| {
+        Ok(Value::Number(args.len() as f64))
+    });
+    script.vm.define_native_fn("shout", Arity::Fixed(1), |vm, args| {
+        let text = args.string(0)?.to_uppercase();
+        Ok(Value::Str(vm.intern(&text)))
+    });
+    script
+}
+
+struct Fail;
+
+impl NativeFunc for Fail {
+    fn name(&self) -> &str {
+        "fail"
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::Fixed(0)
+    }
+
+    fn call(&self, _vm: &mut VM, _args: Args) -> Result<Value, RuntimeError> {
+        Err(RuntimeError::new(RuntimeErrorKind::Native, "it broke"))
+    }
+}
+
+#[test]
+fn natives_are_called_with_their_arguments() {
+    let mut script = with_natives();
+    assert_eq!(
+        script
+            .run("print add(1, 2); print count(1); print count(1, 2, 3); print shout(\"hi\");")
+            .unwrap(),
+        ["3", "1", "3", "HI"]
+    );
+}
+
+#[test]
+fn fixed_arity_must_match_exactly() {
+    let mut script = with_natives();
+    for (source, message) in [
+        ("add(1);", "Expected 2 arguments but got 1"),
+        ("add(1, 2, 3);", "Expected 2 arguments but got 3"),
+    ] {
+        let error = script.runtime_error(source);
+        assert_eq!(error.kind, RuntimeErrorKind::Arity);
+        assert_eq!(error.message, message);
+    }
+}
+
+#[test]
+fn variadic_arity_sets_a_minimum() {
+    let mut script = with_natives();
+    let error = script.runtime_error("count();");
+    assert_eq!(error.kind, RuntimeErrorKind::Arity);
+    assert_eq!(error.message, "Expected at least 1 arguments but got 0");
+}
+
+#[test]
+fn errors_from_natives_name_them_on_the_trace() {
+    let mut script = with_natives();
+    let fail: Rc<dyn NativeFunc> = Rc::new(Fail);
+    script.vm.define_native("fail", &fail);
+    let error = script.runtime_error("fun outer() {\n  fail();\n}\nouter();");
+    assert_eq!(error.kind, RuntimeErrorKind::Native);
+    assert_eq!(error.message, "it broke");
+    let functions: Vec<&str> = error.trace.iter().map(|frame| frame.function.as_str()).collect();
+    assert_eq!(functions[..2], ["fail", "outer"]);
+    assert_eq!(error.trace[0].line, 2);
+}
+
+#[test]
+fn argument_helpers_report_the_expected_type() {
+    let mut script = with_natives();
+    for (source, message) in [
+        ("add(\"1\", 2);", "Argument 1 to 'add' must be a number."),
+        ("add(1, nil);", "Argument 2 to 'add' must be a number."),
+        ("shout(3);", "Argument 1 to 'shout' must be a string."),
+    ] {
+        let error = script.runtime_error(source);
+        assert_eq!(error.kind, RuntimeErrorKind::Type, "{source:?}");
+        assert_eq!(error.message, message);
+        assert_eq!(error.trace[0].function, source[..source.find('(').unwrap()]);
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{output, runtime_error};
+use lox_bytecode::RuntimeErrorKind;
+
+fn eval(expression: &str) -> String {
+    let mut lines = output(&format!("print {expression};"));
+    assert_eq!(lines.len(), 1);
+    lines.remove(0)
+}
+
+#[test]
+fn exponent_binds_tighter_than_unary_minus() {
+    assert_eq!(eval("-2 ** 2"), "-4");
+    assert_eq!(eval("(-2) ** 2"), "4");
+    assert_eq!(eval("2 ** -1"), "0.5");
+}
+
+#[test]
+fn exponent_groups_to_the_right() {
+    assert_eq!(eval("2 ** 3 ** 2"), "512");
+    assert_eq!(eval("(2 ** 3) ** 2"), "64");
+}
+
+#[test]
+fn exponent_binds_tighter_than_factors() {
+    assert_eq!(eval("2 * 3 ** 2"), "18");
+    assert_eq!(eval("3 ** 2 % 5"), "4");
+    assert_eq!(eval("2 ** 10 ~/ 3"), "341");
+}
+
+#[test]
+fn factors_group_to_the_left() {
+    assert_eq!(eval("17 % 5 * 2"), "4");
+    assert_eq!(eval("17 ~/ 5 * 2"), "6");
+    assert_eq!(eval("100 ~/ 10 ~/ 3"), "3");
+    assert_eq!(eval("100 % 7 % 3"), "2");
+    assert_eq!(eval("1 + 7 ~/ 2"), "4");
+    assert_eq!(eval("1 + 7 % 2"), "2");
+}
+
+#[test]
+fn remainder_takes_the_sign_of_the_divisor() {
+    assert_eq!(eval("7 % 3"), "1");
+    assert_eq!(eval("-7 % 3"), "2");
+    assert_eq!(eval("7 % -3"), "-2");
+    assert_eq!(eval("-7 % -3"), "-1");
+    assert_eq!(eval("6 % -3"), "0");
+    assert_eq!(eval("5.5 % 2"), "1.5");
+}
+
+#[test]
+fn integer_division_rounds_toward_negative_infinity() {
+    assert_eq!(eval("7 ~/ 2"), "3");
+    assert_eq!(eval("-7 ~/ 2"), "-4");
+    assert_eq!(eval("7 ~/ -2"), "-4");
+    assert_eq!(eval("-7 ~/ -2"), "3");
+    assert_eq!(eval("7.5 ~/ 2"), "3");
+}
+
+#[test]
+fn division_and_remainder_agree() {
+    for (a, b) in [(7, 3), (-7, 3), (7, -3), (-7, -3), (9, 3)] {
+        assert_eq!(eval(&format!("({a} ~/ {b}) * {b} + {a} % {b} == {a}")), "true");
+    }
+}
+
+#[test]
+fn zero_divisors_follow_ieee() {
+    assert_eq!(eval("7 ~/ 0"), "inf");
+    assert_eq!(eval("-7 ~/ 0"), "-inf");
+    assert_eq!(eval("7 % 0"), "NaN");
+    assert_eq!(eval("-7 % 0"), "NaN");
+    assert_eq!(eval("0 ~/ 0"), "NaN");
+}
+
+
+#[test]
+fn double_slash_still_starts_a_comment() {
+    assert_eq!(output("print 9 ~/ 2; // 9 // 2\n// print 0;\nprint 1;"), ["4", "1"]);
+}
+
+#[test]
+fn operands_must_be_numbers() {
+    for source in ["\"a\" % 2;", "2 ** nil;", "true ~/ 1;", "[1] % 1;"] {
+        let message = runtime_error(source, RuntimeErrorKind::Type);
+        assert_eq!(message, "Operands must be numbers.", "{source:?}");
+    }
+}
+
+#[test]
+fn bitwise_operators_work_on_integers() {
+    assert_eq!(eval("12 & 10"), "8");
+    assert_eq!(eval("12 | 10"), "14");
+    assert_eq!(eval("12 ^ 10"), "6");
+    assert_eq!(eval("~5"), "-6");
+    assert_eq!(eval("-1 & 255"), "255");
+    assert_eq!(eval("1 << 10"), "1024");
+    assert_eq!(eval("-16 >> 2"), "-4");
+    assert_eq!(eval("1 << 0"), "1");
+    assert_eq!(eval("1 << 63 < 0"), "true");
+    assert_eq!(eval("-1 >> 63"), "-1");
+}
+
+#[test]
+fn bitwise_operators_bind_between_comparisons_and_terms() {
+    assert_eq!(eval("6 & 4 == 4"), "true");
+    assert_eq!(eval("1 | 2 ^ 3 & 4"), "3");
+    assert_eq!(eval("1 << 2 + 1"), "8");
+}
+
+#[test]
+fn shift_counts_outside_0_to_63_are_range_errors() {
+    for source in ["1 << 64;", "1 >> 64;", "1 << -1;", "1 >> -1;", "1 << 1000;"] {
+        let message = runtime_error(source, RuntimeErrorKind::Range);
+        assert_eq!(message, "Shift count must be between 0 and 63.", "{source:?}");
+    }
+}
+
+#[test]
+fn bitwise_operands_must_be_integers() {
+    for operator in ["&", "|", "^", "<<", ">>"] {
+        for (a, b) in [("1.5", "1"), ("1", "0.5"), ("\"a\"", "1"), ("1", "nil"), ("true", "1"), ("1", "[1]")] {
+            let source = format!("{a} {operator} {b};");
+            let message = runtime_error(&source, RuntimeErrorKind::Type);
+            assert_eq!(message, "Operands must be integers.", "{source:?}");
+        }
+        // Whole numbers outside the 64-bit range are not integral either.
+        let source = format!("{} {operator} 1;", 2f64.powi(63));
+        runtime_error(&source, RuntimeErrorKind::Type);
+    }
+    for operand in ["1.5", "\"a\"", "nil", "false", "{}"] {
+        let message = runtime_error(&format!("~{operand};"), RuntimeErrorKind::Type);
+        assert_eq!(message, "Operand must be an integer.", "{operand:?}");
+    }
+}
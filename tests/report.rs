@@ -0,0 +1,49 @@
+mod common;
+
+use common::Script;
+use lox_bytecode::{Label, Renderer, Report, Severity, Span};
+
+fn render(source: &str, span: Span) -> String {
+    let report = Report::new(Severity::Error, "oops").with_label(Label::primary(span, "here"));
+    Renderer::new(source).render(&report)
+}
+
+#[test]
+fn labels_past_the_end_are_left_out() {
+    let rendered = render("var a = 1;", Span::new(40, 45, 3, 1));
+    assert_eq!(rendered, "error: oops\n");
+}
+
+#[test]
+fn labels_inside_a_character_are_left_out() {
+    // `é` takes two bytes, so byte 1 is in the middle of it.
+    let rendered = render("é + 1;", Span::new(1, 3, 1, 2));
+    assert_eq!(rendered, "error: oops\n");
+}
+
+#[test]
+fn labels_ending_inside_a_character_are_shortened() {
+    let rendered = render("print \"é\";", Span::new(6, 8, 1, 7));
+    assert!(rendered.contains("1 | print \"é\";"), "{rendered}");
+    assert!(rendered.contains("  |       ^ here"), "{rendered}");
+}
+
+#[test]
+fn labels_at_the_end_of_the_source_are_kept() {
+    let rendered = render("var a", Span::new(5, 5, 1, 6));
+    assert!(rendered.contains("1 | var a"), "{rendered}");
+    assert!(rendered.contains("  |      ^ here"), "{rendered}");
+}
+
+#[test]
+fn traces_render_against_the_source_each_function_came_from() {
+    let mut script = Script::new();
+    script.run("fun f() { return nil + 1; }").unwrap();
+    let line = "var x = 1; f();";
+    let error = script.runtime_error(line);
+    let rendered = Renderer::new(line).render(&Report::from(&error));
+    assert!(rendered.contains("1 | fun f() { return nil + 1; }"), "{rendered}");
+    assert!(rendered.contains("  |                  ^^^^^^^"), "{rendered}");
+    assert!(rendered.contains("1 | var x = 1; f();"), "{rendered}");
+    assert!(rendered.contains("  |            --- in call to f from script"), "{rendered}");
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{runtime_error, Script};
+use lox_bytecode::{RuntimeErrorKind, Value};
+
+/// The number of entries in the weak map held by global `name`.
+fn entries(script: &Script, name: &str) -> usize {
+    let Some(Value::WeakMap(map)) = script.vm.get_global(name) else {
+        panic!("expected a weak map in {name}");
+    };
+    script.vm.object(map).unwrap().len()
+}
+
+#[test]
+fn weak_references_clear_once_their_target_is_collected() {
+    let mut script = Script::new();
+    script.run("class Node {} var a = Node(); var r = weakref(a);").unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(script.run("print deref(r) == a; print r;").unwrap(), ["true", "<weakref>"]);
+
+    script.run("a = nil;").unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(script.run("print deref(r);").unwrap(), ["nil"]);
+}
+
+#[test]
+fn entries_die_with_their_keys() {
+    let mut script = Script::new();
+    script
+        .run(
+            "class Node {}
+             var cache = WeakMap();
+             var kept = Node();
+             var dropped = Node();
+             cache.set(kept, \"kept\");
+             cache.set(dropped, \"dropped\");",
+        )
+        .unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(entries(&script, "cache"), 2);
+
+    script.run("dropped = nil;").unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(entries(&script, "cache"), 1);
+    assert_eq!(
+        script.run("print cache.has(kept); print cache.get(kept);").unwrap(),
+        ["true", "kept"]
+    );
+}
+
+#[test]
+fn values_live_as_long_as_their_keys() {
+    let mut script = Script::new();
+    // The value of `a`'s entry is the only reference to `b`, whose own
+    // entry must survive with it.
+    script
+        .run(
+            "class Node {}
+             var cache = WeakMap();
+             var a = Node();
+             var b = Node();
+             cache.set(a, b);
+             cache.set(b, \"via a\");
+             b = nil;",
+        )
+        .unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(entries(&script, "cache"), 2);
+    assert_eq!(script.run("print cache.get(cache.get(a));").unwrap(), ["via a"]);
+}
+
+#[test]
+fn values_that_refer_to_their_key_do_not_pin_it() {
+    let mut script = Script::new();
+    script
+        .run(
+            "class Box { init(key) { this.key = key; } }
+             var cache = WeakMap();
+             var key = Box(nil);
+             var list = [];
+             var boxed = Box(key);
+             cache.set(key, boxed);
+             cache.set(list, [list]);
+             var value = weakref(boxed);
+             boxed = nil;",
+        )
+        .unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(entries(&script, "cache"), 2);
+
+    script.run("key = nil; list = nil;").unwrap();
+    script.vm.collect_garbage();
+    assert_eq!(entries(&script, "cache"), 0);
+    assert_eq!(script.run("print deref(value);").unwrap(), ["nil"]);
+}
+
+#[test]
+fn only_objects_can_be_weak() {
+    for source in ["WeakMap().set(1, 2);", "WeakMap().set(nil, 2);", "WeakMap().set(true, 2);"] {
+        let message = runtime_error(source, RuntimeErrorKind::Type);
+        assert_eq!(message, "WeakMap keys must be objects.");
+    }
+    let message = runtime_error("weakref(1);", RuntimeErrorKind::Type);
+    assert_eq!(message, "Only objects can be weakly referenced.");
+    let message = runtime_error("deref(1);", RuntimeErrorKind::Type);
+    assert_eq!(message, "Argument 1 to 'deref' must be a weak reference.");
+
+    // Looking up a non-object finds nothing rather than failing.
+    let mut script = Script::new();
+    assert_eq!(
+        script
+            .run("var m = WeakMap(); print m.get(1); print m.has(nil); print m.remove(2);")
+            .unwrap(),
+        ["nil", "false", "nil"]
+    );
+}